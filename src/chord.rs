@@ -0,0 +1,130 @@
+/*
+ * Copyright (C) 2026 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of Monoterm.
+ *
+ * Monoterm is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Monoterm is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Monoterm. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Key chords typed in the parent terminal.
+
+use std::borrow::Cow;
+
+/// The bytes that a key chord sends, e.g., `ESC t` for Alt+T.
+#[derive(Clone, Debug)]
+pub struct Chord(Vec<u8>);
+
+impl Chord {
+    /// Parses a chord of the form `ctrl-<key>`, `alt-<key>`, or
+    /// `ctrl-alt-<key>`, e.g., `ctrl-]`. With `ctrl-`, `<key>` is a letter or
+    /// one of `@[\]^_`; otherwise, it is any printable ASCII character.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let error = || format!("invalid key: {spec}");
+        let mut rest = spec;
+        let mut bytes = Vec::new();
+        let mut ctrl = false;
+        loop {
+            let lower = rest.to_ascii_lowercase();
+            if lower.starts_with("ctrl-") && !ctrl {
+                ctrl = true;
+            } else if lower.starts_with("alt-") && bytes.is_empty() {
+                bytes.push(0x1b);
+            } else {
+                break;
+            }
+            rest = &rest[lower.find('-').unwrap() + 1..];
+        }
+        let &[key] = rest.as_bytes() else {
+            return Err(error());
+        };
+        let byte = match key {
+            _ if !ctrl => key,
+            b'a'..=b'z' => key - b'a' + 1,
+            b'@'..=b'_' => key ^ 0x40,
+            _ => return Err(error()),
+        };
+        if !(ctrl || bytes.len() == 1 && key.is_ascii_graphic()) {
+            return Err(error());
+        }
+        bytes.push(byte);
+        Ok(Self(bytes))
+    }
+
+    /// Removes each occurrence of the chord from `data`. Returns the
+    /// remaining data and the number of occurrences removed.
+    pub fn strip<'a>(&self, data: &'a [u8]) -> (Cow<'a, [u8]>, usize) {
+        let chord = &self.0[..];
+        let mut count = 0;
+        let mut rest = data;
+        let mut stripped = Vec::new();
+        while let Some(i) = rest.windows(chord.len()).position(|w| w == chord)
+        {
+            stripped.extend_from_slice(&rest[..i]);
+            rest = &rest[i + chord.len()..];
+            count += 1;
+        }
+        if count == 0 {
+            return (Cow::Borrowed(data), 0);
+        }
+        stripped.extend_from_slice(rest);
+        (Cow::Owned(stripped), count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(spec: &str) -> Vec<u8> {
+        Chord::parse(spec).unwrap().0
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(bytes("ctrl-]"), b"\x1d");
+        assert_eq!(bytes("CTRL-T"), b"\x14");
+        assert_eq!(bytes("ctrl-t"), b"\x14");
+        assert_eq!(bytes("ctrl-@"), b"\0");
+        assert_eq!(bytes("alt-t"), b"\x1bt");
+        assert_eq!(bytes("alt-T"), b"\x1bT");
+        assert_eq!(bytes("alt--"), b"\x1b-");
+        assert_eq!(bytes("ctrl-alt-x"), b"\x1b\x18");
+        assert_eq!(bytes("Alt-Ctrl-x"), b"\x1b\x18");
+        for spec in [
+            "",
+            "t",
+            "ctrl-",
+            "ctrl-1",
+            "ctrl-tt",
+            "alt- ",
+            "alt-\u{e9}",
+            "ctrl-ctrl-t",
+            "alt-alt-t",
+            "shift-t",
+        ] {
+            assert!(Chord::parse(spec).is_err(), "{spec}");
+        }
+    }
+
+    #[test]
+    fn strip() {
+        let chord = Chord::parse("alt-t").unwrap();
+        let (data, count) = chord.strip(b"abc\x1b");
+        assert!(matches!(data, Cow::Borrowed(b"abc\x1b")));
+        assert_eq!(count, 0);
+        let (data, count) = chord.strip(b"\x1bta\x1b\x1btb\x1bt");
+        assert_eq!(&data[..], b"a\x1bb");
+        assert_eq!(count, 3);
+    }
+}
@@ -0,0 +1,287 @@
+/*
+ * Copyright (C) 2026 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of Monoterm.
+ *
+ * Monoterm is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Monoterm is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Monoterm. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Color specifications and luminance.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A color in the sRGB color space.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// Converts an sRGB component (0 to 1) to linear light.
+fn to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts a linear-light component (0 to 1) to sRGB.
+fn from_linear(c: f64) -> f64 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn parse_hex(digits: &[u8]) -> Option<u32> {
+    let digits = std::str::from_utf8(digits).ok()?;
+    if digits.starts_with('+') {
+        return None;
+    }
+    u32::from_str_radix(digits, 16).ok()
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self {
+            r,
+            g,
+            b,
+        }
+    }
+
+    pub const fn gray(level: u8) -> Self {
+        Self::new(level, level, level)
+    }
+
+    /// Parses a color specification in one of the numeric forms accepted by
+    /// `XParseColor`: `#rgb`, `#rrggbb`, `#rrrgggbbb`, `#rrrrggggbbbb`,
+    /// `rgb:r/g/b` (with one to four hex digits per component), or
+    /// `rgbi:r/g/b` (with components from 0 to 1). Color names are not
+    /// supported.
+    pub fn parse(spec: &[u8]) -> Option<Self> {
+        if let Some(hex) = spec.strip_prefix(b"#") {
+            // Each component holds the most significant bits of the value.
+            let len = hex.len() / 3;
+            if hex.len() % 3 != 0 || !(1..=4).contains(&len) {
+                return None;
+            }
+            let mut iter = hex.chunks(len).map(|c| {
+                parse_hex(c).map(|v| (v << (16 - len * 4) >> 8) as u8)
+            });
+            let mut next = || iter.next().flatten();
+            return Some(Self::new(next()?, next()?, next()?));
+        }
+
+        let i = spec.iter().position(|b| *b == b':')?;
+        let (format, rest) = (&spec[..i], &spec[i + 1..]);
+        let mut parts = rest.split(|b| *b == b'/');
+        let mut component = || {
+            let c = parts.next()?;
+            let value = match format {
+                b"rgb" if (1..=4).contains(&c.len()) => {
+                    let max = (1_u32 << (c.len() * 4)) - 1;
+                    f64::from(parse_hex(c)?) / f64::from(max)
+                }
+                b"rgbi" => {
+                    let value: f64 =
+                        std::str::from_utf8(c).ok()?.parse().ok()?;
+                    (0.0..=1.0).contains(&value).then_some(value)?
+                }
+                _ => return None,
+            };
+            Some((value * 255.0).round() as u8)
+        };
+        let color = Self::new(component()?, component()?, component()?);
+        parts.next().is_none().then_some(color)
+    }
+
+    /// Returns the relative luminance of the color, from 0 (black) to 1
+    /// (white), using the Rec. 709 coefficients.
+    pub fn luminance(self) -> f64 {
+        let [r, g, b] =
+            [self.r, self.g, self.b].map(|c| to_linear(f64::from(c) / 255.0));
+        0.2126 * r + 0.7152 * g + 0.0722 * b
+    }
+
+    /// Returns the gray whose luminance is `luminance` (from 0 to 1).
+    pub fn from_luminance(luminance: f64) -> Self {
+        Self::gray(
+            (from_linear(luminance.clamp(0.0, 1.0)) * 255.0).round() as u8
+        )
+    }
+
+    /// Returns the gray with the same luminance as this color.
+    pub fn to_gray(self) -> Self {
+        Self::from_luminance(self.luminance())
+    }
+
+    /// Returns the contrast ratio between this color and `other`, from 1 to
+    /// 21, as defined by WCAG 2.
+    pub fn contrast(self, other: Self) -> f64 {
+        let (a, b) = (self.luminance(), other.luminance());
+        (a.max(b) + 0.05) / (a.min(b) + 0.05)
+    }
+
+    /// Converts the color to CIELAB (with a D65 white point).
+    pub fn to_lab(self) -> [f64; 3] {
+        let [r, g, b] =
+            [self.r, self.g, self.b].map(|c| to_linear(f64::from(c) / 255.0));
+        let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+        let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+        let f = |t: f64| {
+            if t > 216.0 / 24389.0 {
+                t.cbrt()
+            } else {
+                (24389.0 / 27.0 * t + 16.0) / 116.0
+            }
+        };
+        let [x, y, z] = [x, y, z].map(f);
+        [116.0 * y - 16.0, 500.0 * (x - y), 200.0 * (y - z)]
+    }
+
+    /// Formats the color as an X11 color specification with `digits` (1 to
+    /// 4) hex digits per component, e.g., `rgb:ffff/8080/0000`.
+    pub fn to_x11(self, digits: usize) -> String {
+        let digits = digits.clamp(1, 4);
+        let max = (1_u32 << (digits * 4)) - 1;
+        let [r, g, b] =
+            [self.r, self.g, self.b].map(|c| (u32::from(c) * max + 127) / 255);
+        format!("rgb:{r:0digits$x}/{g:0digits$x}/{b:0digits$x}")
+    }
+}
+
+/// A color set by SGR: either an index into the 256-color palette or a
+/// direct (truecolor) value.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Color {
+    Indexed(u8),
+    Rgb(Rgb),
+}
+
+impl fmt::Display for Color {
+    /// Formats the color as a palette index or as `#rrggbb`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Indexed(i) => write!(f, "{i}"),
+            Self::Rgb(Rgb {
+                r,
+                g,
+                b,
+            }) => write!(f, "#{r:02x}{g:02x}{b:02x}"),
+        }
+    }
+}
+
+/// A set of gray levels (sRGB values from 0 to 255) that grays are snapped
+/// to, for displays that can show only a few levels.
+#[derive(Clone, Debug)]
+pub struct Levels(Vec<u8>);
+
+impl Levels {
+    /// Returns `n` (at least 2) evenly spaced levels from black to white.
+    pub fn evenly_spaced(n: usize) -> Self {
+        let n = n.clamp(2, 256);
+        Self(
+            (0..n)
+                .map(|i| ((i * 255) as f64 / (n - 1) as f64).round() as u8)
+                .collect(),
+        )
+    }
+
+    /// Parses either a number of evenly spaced levels, e.g., `4`, or a
+    /// comma-separated list of levels, e.g., `0,96,192,255`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let error = || format!("invalid gray levels: {spec}");
+        if !spec.contains(',') {
+            return match spec.parse() {
+                Ok(n @ 2..=256) => Ok(Self::evenly_spaced(n)),
+                _ => Err(error()),
+            };
+        }
+        let mut levels = spec
+            .split(',')
+            .map(|s| s.parse().map_err(|_| error()))
+            .collect::<Result<Vec<u8>, _>>()?;
+        levels.sort_unstable();
+        levels.dedup();
+        if levels.len() < 2 {
+            return Err(error());
+        }
+        Ok(Self(levels))
+    }
+
+    /// Keeps only the first of each run of adjacent levels with the same
+    /// `key`, e.g., levels that would be shown as the same palette color.
+    /// Returns `None` if fewer than two levels remain.
+    pub fn dedup_by_key<K: PartialEq>(
+        mut self,
+        mut key: impl FnMut(u8) -> K,
+    ) -> Option<Self> {
+        self.0.dedup_by_key(|level| key(*level));
+        (self.0.len() >= 2).then_some(self)
+    }
+
+    /// Returns the index of the level closest to `gray`.
+    fn nearest(&self, gray: u8) -> usize {
+        (0..self.0.len())
+            .min_by_key(|i| self.0[*i].abs_diff(gray))
+            .unwrap_or(0)
+    }
+
+    /// Returns the level closest to `gray`.
+    pub fn snap_gray(&self, gray: u8) -> u8 {
+        self.0[self.nearest(gray)]
+    }
+
+    /// Snaps `gray` to a level, moving it to an adjacent level if it would
+    /// land on the same one as `other` (e.g., a foreground on the level of
+    /// its background).
+    pub fn snap_apart(&self, gray: u8, other: u8) -> u8 {
+        let (level, other_level) = (self.nearest(gray), self.nearest(other));
+        if level != other_level {
+            return self.0[level];
+        }
+        // Keep `gray` on the side of `other` it was on.
+        let up = match gray.cmp(&other) {
+            Ordering::Less => false,
+            Ordering::Greater => true,
+            Ordering::Equal => other_level < self.0.len() / 2,
+        };
+        self.0[match (up, level) {
+            (false, 0) => 1,
+            (false, i) => i - 1,
+            (true, i) if i + 1 == self.0.len() => i - 1,
+            (true, i) => i + 1,
+        }]
+    }
+}
+
+impl fmt::Display for Levels {
+    /// Formats the levels as a comma-separated list.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, level) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{level}")?;
+        }
+        Ok(())
+    }
+}
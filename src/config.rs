@@ -0,0 +1,303 @@
+/*
+ * Copyright (C) 2026 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of Monoterm.
+ *
+ * Monoterm is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Monoterm is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Monoterm. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The configuration file, `monoterm/config.toml` in the user's config
+//! directory.
+//!
+//! The file is a subset of TOML. Top-level keys are the long names of
+//! options, and `[profiles.<name>]` tables hold named profiles that override
+//! them:
+//!
+//! ```toml
+//! bold = true
+//! keep = ["red", "bright-red"]
+//!
+//! [profiles.eink]
+//! levels = 4
+//! ```
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A setting in the configuration file. Values are converted to the form
+/// they'd have as option values: booleans become `true` or `false`, and
+/// arrays become comma-separated lists.
+pub struct Entry {
+    pub key: String,
+    pub value: String,
+    pub line: usize,
+}
+
+pub struct Config {
+    /// The settings outside any profile.
+    pub default: Vec<Entry>,
+    pub profiles: Vec<(String, Vec<Entry>)>,
+}
+
+/// Parses a basic (`"..."`) or literal (`'...'`) string at the start of `s`.
+/// Returns the string and the rest of `s`.
+fn parse_string(s: &str) -> Option<(String, &str)> {
+    let quote = s.chars().next().filter(|c| matches!(c, '"' | '\''))?;
+    let mut chars = s.char_indices().skip(1);
+    let mut string = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            _ if c == quote => return Some((string, &s[i + 1..])),
+            '\\' if quote == '"' => {
+                string.push(match chars.next()?.1 {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    c @ ('"' | '\\') => c,
+                    c @ ('u' | 'U') => {
+                        let len = if c == 'u' {
+                            4
+                        } else {
+                            8
+                        };
+                        let start = chars.next()?.0;
+                        let digits = s.get(start..start + len)?;
+                        (1..len).try_for_each(|_| chars.next().map(drop))?;
+                        char::from_u32(u32::from_str_radix(digits, 16).ok()?)?
+                    }
+                    _ => return None,
+                });
+            }
+            _ => string.push(c),
+        }
+    }
+    None
+}
+
+/// Parses a single value at the start of `s`. Returns the value and the rest
+/// of `s`.
+fn parse_value(s: &str) -> Option<(String, &str)> {
+    if let Some(string) = parse_string(s) {
+        return Some(string);
+    }
+    if let Some(mut rest) = s.strip_prefix('[') {
+        let mut items = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(rest) = rest.strip_prefix(']') {
+                return Some((items.join(","), rest));
+            }
+            let (item, after) = parse_value(rest)?;
+            items.push(item);
+            rest = after.trim_start();
+            if let Some(after) = rest.strip_prefix(',') {
+                rest = after;
+            } else if !rest.starts_with(']') {
+                return None;
+            }
+        }
+    }
+    let end = s.find([',', ']', ' ', '\t', '#']).unwrap_or(s.len());
+    let (value, rest) = s.split_at(end);
+    let valid = matches!(value, "true" | "false")
+        || value
+            .strip_prefix(['+', '-'])
+            .unwrap_or(value)
+            .parse::<u64>()
+            .is_ok();
+    valid.then(|| (value.to_owned(), rest))
+}
+
+/// Parses a key, which is either bare or quoted.
+fn parse_key(s: &str) -> Option<String> {
+    let s = s.trim();
+    if let Some((key, rest)) = parse_string(s) {
+        return rest.trim().is_empty().then_some(key);
+    }
+    let bare = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    (!s.is_empty() && s.chars().all(bare)).then(|| s.to_owned())
+}
+
+impl Config {
+    /// Returns the default path of the configuration file:
+    /// `$XDG_CONFIG_HOME/monoterm/config.toml`, or
+    /// `~/.config/monoterm/config.toml` if `XDG_CONFIG_HOME` isn't set.
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(dir.join("monoterm").join("config.toml"))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut config = Self {
+            default: Vec::new(),
+            profiles: Vec::new(),
+        };
+        let mut profile = None;
+        for (i, line) in text.lines().enumerate() {
+            let line_num = i + 1;
+            let error = |msg: &str| format!("line {line_num}: {msg}");
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[') {
+                let (name, rest) = header
+                    .split_once(']')
+                    .ok_or_else(|| error("invalid table header"))?;
+                let rest = rest.trim();
+                if !(rest.is_empty() || rest.starts_with('#')) {
+                    return Err(error("invalid table header"));
+                }
+                let name = name
+                    .trim()
+                    .strip_prefix("profiles.")
+                    .and_then(parse_key)
+                    .ok_or_else(|| error("unknown table"))?;
+                let index = config.profiles.iter().position(|p| p.0 == name);
+                profile = Some(index.unwrap_or_else(|| {
+                    config.profiles.push((name, Vec::new()));
+                    config.profiles.len() - 1
+                }));
+                continue;
+            }
+
+            let (key, value) =
+                line.split_once('=').ok_or_else(|| error("expected '='"))?;
+            // Underscores in option names are equivalent to hyphens.
+            let key = parse_key(key)
+                .map(|key| key.replace('_', "-"))
+                .ok_or_else(|| error("invalid key"))?;
+            let (value, rest) = parse_value(value.trim())
+                .ok_or_else(|| error("invalid value"))?;
+            let rest = rest.trim();
+            if !(rest.is_empty() || rest.starts_with('#')) {
+                return Err(error("unexpected data after value"));
+            }
+            let entries = match profile {
+                Some(i) => &mut config.profiles[i].1,
+                None => &mut config.default,
+            };
+            entries.push(Entry {
+                key,
+                value,
+                line: line_num,
+            });
+        }
+        Ok(config)
+    }
+
+    /// Loads the configuration file at `path`. Returns `Ok(None)` if the file
+    /// doesn't exist.
+    pub fn load(path: &Path) -> Result<Option<Self>, String> {
+        let display = path.display();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("could not read {display}: {e}")),
+        };
+        Self::parse(&text).map(Some).map_err(|e| format!("{display}: {e}"))
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&[Entry]> {
+        self.profiles.iter().find(|p| p.0 == name).map(|p| &p.1[..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(entries: &[Entry]) -> Vec<(&str, &str)> {
+        entries.iter().map(|e| (&e.key[..], &e.value[..])).collect()
+    }
+
+    #[test]
+    fn values() {
+        let config = Config::parse(
+            "bold = true # comment\n\
+             levels = 4\n\
+             map = \"red=bold, \\\"x\\\"\\t\\u00e9\\U0001F600\\\\\"\n\
+             palette = '~\\p'\n\
+             keep = [ \"red\", 'blue',9 ] # comment\n\
+             x = []\n\
+             y = -1\n",
+        )
+        .unwrap();
+        assert_eq!(
+            entries(&config.default),
+            [
+                ("bold", "true"),
+                ("levels", "4"),
+                ("map", "red=bold, \"x\"\t\u{e9}\u{1f600}\\"),
+                ("palette", "~\\p"),
+                ("keep", "red,blue,9"),
+                ("x", ""),
+                ("y", "-1"),
+            ],
+        );
+    }
+
+    #[test]
+    fn keys() {
+        let config = Config::parse(
+            "no_color = true\n\
+             \"tool-colors\" = false\n\
+             [profiles.my_eink] # comment\n\
+             truecolor = true\n\
+             [profiles.\"a b\"]\n\
+             bold = false\n\
+             [ profiles.my_eink ]\n\
+             levels = 2\n",
+        )
+        .unwrap();
+        assert_eq!(
+            entries(&config.default),
+            [("no-color", "true"), ("tool-colors", "false")],
+        );
+        let names: Vec<_> = config.profiles.iter().map(|p| &p.0).collect();
+        assert_eq!(names, ["my_eink", "a b"]);
+        assert_eq!(
+            entries(config.profile("my_eink").unwrap()),
+            [("truecolor", "true"), ("levels", "2")],
+        );
+    }
+
+    #[test]
+    fn errors() {
+        for text in [
+            "bold",
+            "bold = yes",
+            "bold = true false",
+            "map = \"x",
+            "map = \"\\x\"",
+            "map = \"\\u12\"",
+            "keep = [\"red\" \"blue\"]",
+            "keep = [\"red\"",
+            "a.b = true",
+            "[other]",
+            "[profiles.a] x",
+            "[profiles.a b]",
+        ] {
+            assert!(Config::parse(text).is_err(), "{text}");
+        }
+        let error = Config::parse("\n\nbold = yes").err().unwrap();
+        assert_eq!(error, "line 3: invalid value");
+    }
+}
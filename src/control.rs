@@ -0,0 +1,128 @@
+/*
+ * Copyright (C) 2026 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of Monoterm.
+ *
+ * Monoterm is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Monoterm is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Monoterm. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The control socket, through which other programs can query and change a
+//! running session.
+//!
+//! Clients send commands as lines of text. Each command's reply consists of
+//! zero or more lines of output followed by `ok` or `error: <message>`.
+//! The commands are:
+//!
+//! * `status`: reports the settings and the child's graphic rendition.
+//! * `set <option> <value>`: sets an option, e.g., `set mode grayscale` or
+//!   `set bold on`.
+//! * `pause` and `resume`: disable and re-enable filtering.
+//! * `reload`: reloads the configuration file.
+
+use crate::Reloader;
+use std::fs;
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+
+/// Runs `command` and returns its output.
+fn run_command(command: &str, reloader: &Reloader) -> Result<String, String> {
+    let mut words = command.split_whitespace();
+    let name = words.next().unwrap_or_default();
+    let args: Vec<_> = words.collect();
+    let no_args = || {
+        if args.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("unexpected arguments to {name}"))
+        }
+    };
+    match name {
+        "status" => {
+            no_args()?;
+            let profile = reloader.profile();
+            let profile = profile.as_deref().unwrap_or("none");
+            Ok(format!("{}profile: {profile}\n", reloader.control.status()))
+        }
+        "set" => match &args[..] {
+            [option, value @ ..] if !value.is_empty() => {
+                reloader.set(option, &value.join(" ")).map(|_| String::new())
+            }
+            _ => Err("usage: set <option> <value>".to_owned()),
+        },
+        "pause" | "resume" => {
+            no_args()?;
+            reloader.control.set_enabled(name == "resume");
+            Ok(String::new())
+        }
+        "reload" => {
+            no_args()?;
+            reloader.reload().map(|_| String::new())
+        }
+        _ => Err(format!("unknown command: {name}")),
+    }
+}
+
+fn handle_client(stream: UnixStream, reloader: &Reloader) -> io::Result<()> {
+    let mut writer = &stream;
+    for line in BufReader::new(&stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match run_command(&line, reloader) {
+            Ok(output) => writeln!(writer, "{output}ok")?,
+            Err(e) => writeln!(writer, "error: {e}")?,
+        }
+    }
+    Ok(())
+}
+
+/// Binds a socket at `path`, replacing it if it's left over from a session
+/// that has ended. Files other than sockets are never replaced.
+fn bind(path: &Path) -> io::Result<UnixListener> {
+    match UnixListener::bind(path) {
+        Err(e) if e.kind() == ErrorKind::AddrInUse => {
+            let is_socket = fs::symlink_metadata(path)
+                .is_ok_and(|m| m.file_type().is_socket());
+            if !is_socket || UnixStream::connect(path).is_ok() {
+                return Err(e);
+            }
+            fs::remove_file(path)?;
+            UnixListener::bind(path)
+        }
+        result => result,
+    }
+}
+
+/// Listens for clients on a socket at `path`, handling them in background
+/// threads.
+pub fn listen(path: &Path, reloader: Arc<Reloader>) -> Result<(), String> {
+    let listener = bind(path).map_err(|e| {
+        format!("could not create socket {}: {e}", path.display())
+    })?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let reloader = reloader.clone();
+            thread::spawn(move || handle_client(stream, &reloader));
+        }
+    });
+    Ok(())
+}
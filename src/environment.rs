@@ -0,0 +1,144 @@
+/*
+ * Copyright (C) 2026 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of Monoterm.
+ *
+ * Monoterm is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Monoterm is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Monoterm. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Changes to the child's environment that tell programs to avoid color.
+
+use crate::color::Rgb;
+use std::env;
+
+/// The changes made by default: `NO_COLOR=1`, removing `COLORTERM`, and
+/// setting `COLORFGBG`.
+pub const DEFAULT_CHANGES: &str = "NO_COLOR,-COLORTERM,COLORFGBG";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Change {
+    Set(String, String),
+    Remove(String),
+    /// Set `COLORFGBG` to white on black or black on white, according to the
+    /// default background.
+    ColorFgBg,
+}
+
+/// Returns whether `name` can be the name of an environment variable.
+fn is_valid_name(name: &str) -> bool {
+    !(name.is_empty() || name.contains(['=', '\0']))
+}
+
+/// Parses an item of an [`EnvChanges`] list.
+fn parse_change(item: &str) -> Option<Change> {
+    match item {
+        "NO_COLOR" => Some(Change::Set(item.to_owned(), "1".to_owned())),
+        "COLORFGBG" => Some(Change::ColorFgBg),
+        _ => match item.split_once('=') {
+            Some((name, value)) => {
+                let valid = is_valid_name(name) && !value.contains('\0');
+                valid.then(|| Change::Set(name.to_owned(), value.to_owned()))
+            }
+            None => item
+                .strip_prefix('-')
+                .filter(|name| is_valid_name(name))
+                .map(|name| Change::Remove(name.to_owned())),
+        },
+    }
+}
+
+/// A list of changes to environment variables.
+#[derive(Clone, Debug)]
+pub struct EnvChanges(Vec<Change>);
+
+impl EnvChanges {
+    /// Parses a comma-separated list of changes, each of which is
+    /// `<name>=<value>`, `-<name>` (to remove the variable), `NO_COLOR` (to
+    /// set it to 1), or `COLORFGBG` (to match the default background).
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        spec.split(',')
+            .map(|item| {
+                parse_change(item).ok_or_else(|| {
+                    format!("invalid environment change: {item}")
+                })
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+
+    /// Applies the changes to this process's environment, which the child
+    /// inherits. `background` is the terminal's default background.
+    ///
+    /// This must be called before any other threads are started.
+    pub fn apply(&self, background: Rgb) {
+        for change in &self.0 {
+            match change {
+                Change::Set(name, value) => env::set_var(name, value),
+                Change::Remove(name) => env::remove_var(name),
+                Change::ColorFgBg => {
+                    let white = Rgb::gray(255);
+                    let black = Rgb::gray(0);
+                    let dark = background.contrast(white)
+                        > background.contrast(black);
+                    env::set_var(
+                        "COLORFGBG",
+                        if dark {
+                            "15;0"
+                        } else {
+                            "0;15"
+                        },
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl Default for EnvChanges {
+    fn default() -> Self {
+        Self::parse(DEFAULT_CHANGES).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(name: &str, value: &str) -> Change {
+        Change::Set(name.to_owned(), value.to_owned())
+    }
+
+    #[test]
+    fn parse() {
+        let changes = EnvChanges::parse(
+            "NO_COLOR,-COLORTERM,COLORFGBG,TERM=xterm-mono,X=a=b,Y=",
+        )
+        .unwrap();
+        assert_eq!(
+            changes.0,
+            [
+                set("NO_COLOR", "1"),
+                Change::Remove("COLORTERM".to_owned()),
+                Change::ColorFgBg,
+                set("TERM", "xterm-mono"),
+                set("X", "a=b"),
+                set("Y", ""),
+            ],
+        );
+        assert_eq!(EnvChanges::default().0.len(), 3);
+        for spec in ["", "X", "-", "=x", "X=\0", "-X\0", "a,,b"] {
+            assert!(EnvChanges::parse(spec).is_err(), "{spec:?}");
+        }
+    }
+}
@@ -0,0 +1,1598 @@
+/*
+ * Copyright (C) 2021-2022, 2024, 2026 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of Monoterm.
+ *
+ * Monoterm is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Monoterm is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Monoterm. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::chord::Chord;
+use crate::color::{Color, Levels, Rgb};
+use crate::osc::{OscAction, OscActions, OscFilter};
+use crate::palette::Palette;
+use crate::parser::{C1Mode, Csi, Parser, Perform};
+use crate::style::{ColorMap, ColorSet, Intensity, Style, Underline};
+use std::borrow::Cow;
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+/// The minimum contrast ratio between a background color and the default
+/// background for the color to be shown as reverse video (in mono mode).
+/// Background colors closer to the default are ignored.
+const BACKGROUND_CONTRAST: f64 = 1.5;
+
+/// With emphasis enabled (in mono mode), colored foregrounds whose contrast
+/// ratio with the default background is below `FAINT_CONTRAST` become faint,
+/// and those at or above `BOLD_CONTRAST` become bold.
+const FAINT_CONTRAST: f64 = 3.0;
+const BOLD_CONTRAST: f64 = 10.0;
+
+/// With emphasis enabled (in mono mode), gray foregrounds become faint if
+/// their contrast ratio with the default background is below this fraction of
+/// the default foreground's.
+const DIM_GRAY_CONTRAST: f64 = 0.75;
+
+/// Maximum number of bytes of arguments held back for a streamed SGR
+/// sequence. Only arguments that aren't interpreted (colors and styles are
+/// tracked in [`Attributes`]) are held back, and SGR 0 discards them, so this
+/// is reached only by unusual sequences; further arguments are dropped.
+const DEFERRED_ARGS_MAX_LEN: usize = 128;
+
+/// Maximum number of bytes of parameters kept for a streamed control
+/// sequence, which are forwarded if it turns out not to be SGR. Terminals
+/// ignore all but the first few dozen parameters, so further parameters are
+/// dropped.
+const STREAMED_PARAMS_MAX_LEN: usize = 1024;
+
+/// The graphic rendition requested by the child.
+#[derive(Clone, Copy)]
+struct Attributes {
+    background: Option<Color>,
+    video_reversed: bool,
+    foreground: Option<Color>,
+    underline_color: Option<Color>,
+    style: Style,
+}
+
+impl Attributes {
+    pub const DEFAULT: Self = Self {
+        background: None,
+        video_reversed: false,
+        foreground: None,
+        underline_color: None,
+        style: Style::DEFAULT,
+    };
+}
+
+/// The colors that the parent terminal is set to (in grayscale and
+/// downsampling modes), or `None` for the default colors.
+#[derive(Clone, Copy, Eq, PartialEq)]
+struct Colors {
+    foreground: Option<Color>,
+    background: Option<Color>,
+    underline: Option<Color>,
+}
+
+impl Colors {
+    pub const DEFAULT: Self = Self {
+        foreground: None,
+        background: None,
+        underline: None,
+    };
+}
+
+/// What an SGR color argument sets.
+#[derive(Clone, Copy)]
+enum Target {
+    Foreground,
+    Background,
+    Underline,
+}
+
+/// A semicolon-separated extended color (38, 48, or 58) whose arguments are
+/// being received. (The colon-separated forms, e.g., `38:2::255:0:0`, hold
+/// all of their subparameters in a single argument.)
+#[derive(Clone, Copy)]
+enum Pending {
+    None,
+    /// The next argument selects the color format.
+    Format(Target),
+    /// The next argument is a palette index.
+    Index(Target),
+    /// The components of a direct color received so far.
+    Rgb(Target, [u8; 3], usize),
+}
+
+/// Parses a numeric (sub)parameter, which is 0 if empty.
+fn parse_param(param: &[u8]) -> Option<u32> {
+    match param {
+        [] => Some(0),
+        _ => std::str::from_utf8(param).ok()?.parse().ok(),
+    }
+}
+
+/// Parses the subparameters that follow 38, 48, or 58 in the colon-separated
+/// form of an extended color, e.g., `5:n` or `2::r:g:b`.
+fn parse_colon_color<'a, I>(subparams: I) -> Option<Color>
+where
+    I: Iterator<Item = &'a [u8]>,
+{
+    let values: Vec<_> = subparams.map(parse_param).collect();
+    let byte = |i: usize| {
+        values.get(i).copied().flatten().and_then(|n| u8::try_from(n).ok())
+    };
+    match values.first().copied().flatten()? {
+        5 => Some(Color::Indexed(byte(1)?)),
+        2 => {
+            // The color space ID precedes the components, but some programs
+            // omit it (`2:r:g:b`).
+            let i = if values.len() == 4 {
+                1
+            } else {
+                2
+            };
+            Some(Color::Rgb(Rgb::new(byte(i)?, byte(i + 1)?, byte(i + 2)?)))
+        }
+        _ => None,
+    }
+}
+
+/// The state of an SGR sequence that is being processed.
+struct SgrProgress {
+    /// The parent terminal's colors, video reversal, and style before the
+    /// sequence.
+    colors: Colors,
+    reversed: bool,
+    style: Style,
+    /// Whether any arguments have been written.
+    any_written: bool,
+    pending: Pending,
+    /// The attributes before the sequence, restored if it is canceled.
+    saved: Attributes,
+    /// For a streamed sequence, the `;`-separated arguments to write once it
+    /// is known to be SGR. (Until then, nothing is written.)
+    deferred: Option<Vec<u8>>,
+    /// For a streamed sequence, the parameters received so far, each followed
+    /// by `;`, and whether any have been dropped.
+    params: Vec<u8>,
+    params_truncated: bool,
+}
+
+impl SgrProgress {
+    fn write_arg(&mut self, arg: &[u8], write: &mut dyn FnMut(&[u8])) {
+        if let Some(deferred) = &mut self.deferred {
+            if deferred.len() + arg.len() < DEFERRED_ARGS_MAX_LEN {
+                if !deferred.is_empty() {
+                    deferred.push(b';');
+                }
+                deferred.extend_from_slice(arg);
+            }
+            return;
+        }
+        write(if mem::replace(&mut self.any_written, true) {
+            b";"
+        } else {
+            b"\x1b["
+        });
+        write(arg);
+    }
+
+    /// Called for SGR 0, which cancels the effect of any arguments before it.
+    fn reset(&mut self) {
+        if let Some(deferred) = &mut self.deferred {
+            deferred.clear();
+        }
+    }
+
+    /// Writes the deferred arguments of a streamed sequence. Later arguments
+    /// are written directly.
+    fn write_deferred(&mut self, write: &mut dyn FnMut(&[u8])) {
+        if let Some(deferred) = self.deferred.take() {
+            if !deferred.is_empty() {
+                self.write_arg(&deferred, write);
+            }
+        }
+    }
+
+    /// Adds the parameters `params` of a streamed sequence, each followed by
+    /// `;`, to [`Self::params`].
+    fn push_params(&mut self, params: &[u8]) {
+        if self.params.len() + params.len() > STREAMED_PARAMS_MAX_LEN {
+            self.params_truncated = true;
+        }
+        if !self.params_truncated {
+            self.params.extend_from_slice(params);
+        }
+    }
+}
+
+/// Tracks the graphic rendition requested by the child and rewrites SGR
+/// sequences accordingly.
+struct SgrFilter {
+    settings: SgrSettings,
+    /// Whether filtering is enabled. If not, the child's output is passed
+    /// through unchanged, and the parent terminal's rendition is the child's.
+    enabled: bool,
+    attrs: Attributes,
+    /// The SGR sequence whose parameters are being streamed, if any.
+    progress: Option<SgrProgress>,
+}
+
+impl SgrFilter {
+    /// Returns whether `color` contrasts enough with the default background
+    /// to be shown (in mono mode) as reverse video.
+    fn is_contrasting_background(&self, color: Color) -> bool {
+        let background = self.settings.palette.background;
+        self.settings.palette.rgb(color).contrast(background)
+            >= BACKGROUND_CONTRAST
+    }
+
+    /// Returns whether the child's colors are shown (in mono mode) as reverse
+    /// video.
+    fn is_reversed_by_colors(&self) -> bool {
+        if self.settings.mode != Mode::Mono {
+            return false;
+        }
+        let Some(background) = self.attrs.background.filter(|c| {
+            !self.is_kept(*c) && self.is_contrasting_background(*c)
+        }) else {
+            return false;
+        };
+        let Some(foreground) = self.attrs.foreground else {
+            return true;
+        };
+        if self.is_kept(foreground) {
+            // Show the kept color on the default background.
+            return false;
+        }
+        // Keep the polarity (light on dark or dark on light) of the colors.
+        let palette = &self.settings.palette;
+        let dark_on_light = |fg: Rgb, bg: Rgb| fg.luminance() < bg.luminance();
+        dark_on_light(palette.rgb(foreground), palette.rgb(background))
+            != dark_on_light(palette.foreground, palette.background)
+    }
+
+    /// Returns whether `color` is forwarded unchanged (in mono and grayscale
+    /// modes).
+    fn is_kept(&self, color: Color) -> bool {
+        self.settings.keep.contains(color, &self.settings.palette)
+    }
+
+    fn parent_video_reversed(&self) -> bool {
+        if !self.enabled {
+            return self.attrs.video_reversed;
+        }
+        self.is_reversed_by_colors() != self.attrs.video_reversed
+    }
+
+    /// Returns whether `color` is a shade of gray rather than a color.
+    fn is_gray(&self, color: Color) -> bool {
+        let Rgb {
+            r,
+            g,
+            b,
+        } = self.settings.palette.rgb(color);
+        r == g && g == b
+    }
+
+    /// Returns the intensity that represents the foreground `color` when
+    /// emphasis is enabled.
+    fn emphasis(&self, color: Color) -> Intensity {
+        let palette = &self.settings.palette;
+        let contrast = palette.rgb(color).contrast(palette.background);
+        if self.is_gray(color) {
+            let default = palette.foreground.contrast(palette.background);
+            if contrast < default * DIM_GRAY_CONTRAST {
+                Intensity::Low
+            } else {
+                Intensity::Normal
+            }
+        } else if contrast < FAINT_CONTRAST {
+            Intensity::Low
+        } else if contrast >= BOLD_CONTRAST {
+            Intensity::High
+        } else {
+            Intensity::Normal
+        }
+    }
+
+    /// Returns the style that replaces the foreground `color` (in mono mode).
+    fn foreground_style(&self, color: Color) -> Style {
+        if let Some(style) =
+            self.settings.color_map.get(color, &self.settings.palette)
+        {
+            return style;
+        }
+        // The basic colors count as colors even if they're gray.
+        let colored =
+            matches!(color, Color::Indexed(0..=15)) || !self.is_gray(color);
+        let intensity = if self.settings.emphasis {
+            self.emphasis(color)
+        } else if self.settings.bold_colors && colored {
+            Intensity::High
+        } else {
+            Intensity::Normal
+        };
+        Style {
+            intensity,
+            ..Style::DEFAULT
+        }
+    }
+
+    fn parent_style(&self) -> Style {
+        if !self.enabled {
+            return self.attrs.style;
+        }
+        let mapped = match self.attrs.foreground {
+            Some(color)
+                if self.settings.mode == Mode::Mono
+                    && !self.is_kept(color) =>
+            {
+                self.foreground_style(color)
+            }
+            _ => Style::DEFAULT,
+        };
+        self.attrs.style.merge(mapped)
+    }
+
+    /// Returns the palette color closest to `color` among the first `n`.
+    fn downsample(&self, color: Color, n: u16) -> Color {
+        let max = (n - 1) as u8;
+        Color::Indexed(match color {
+            Color::Indexed(i) if i <= max => i,
+            color => self
+                .settings
+                .palette
+                .nearest(self.settings.palette.rgb(color), 0..=max),
+        })
+    }
+
+    /// Returns the color that represents the gray level `gray`.
+    fn gray_color(&self, gray: u8) -> Color {
+        if self.settings.truecolor_grays {
+            Color::Rgb(Rgb::gray(gray))
+        } else {
+            Color::Indexed(self.settings.palette.nearest_gray(gray))
+        }
+    }
+
+    fn parent_colors(&self) -> Colors {
+        let attrs = &self.attrs;
+        if !self.enabled {
+            return Colors {
+                foreground: attrs.foreground,
+                background: attrs.background,
+                underline: attrs.underline_color,
+            };
+        }
+        let kept = |c: Option<Color>| c.filter(|c| self.is_kept(*c));
+        match self.settings.mode {
+            Mode::Mono => Colors {
+                foreground: kept(attrs.foreground),
+                background: kept(attrs.background),
+                underline: kept(attrs.underline_color),
+            },
+            Mode::Grayscale => {
+                let gray = |c: Option<Color>| {
+                    let c = c.filter(|c| !self.is_kept(*c));
+                    c.map(|c| self.settings.palette.rgb(c).to_gray().r)
+                };
+                let mut foreground = gray(attrs.foreground);
+                let mut background = gray(attrs.background);
+                let mut underline = gray(attrs.underline_color);
+                if let Some(levels) = &self.settings.levels {
+                    // The default colors count too: text in the default
+                    // foreground color must stay visible on a background
+                    // color, and vice versa.
+                    let default_fg =
+                        self.settings.palette.foreground.to_gray().r;
+                    let default_bg =
+                        self.settings.palette.background.to_gray().r;
+                    (foreground, background) = match (foreground, background) {
+                        (Some(fg), bg) => {
+                            let other = bg.unwrap_or(default_bg);
+                            let fg = levels.snap_apart(fg, other);
+                            (Some(fg), bg.map(|g| levels.snap_gray(g)))
+                        }
+                        (None, Some(bg)) => {
+                            (None, Some(levels.snap_apart(bg, default_fg)))
+                        }
+                        (None, None) => (None, None),
+                    };
+                    underline = underline.map(|g| levels.snap_gray(g));
+                }
+                let color = |g: Option<u8>| g.map(|g| self.gray_color(g));
+                Colors {
+                    foreground: kept(attrs.foreground).or(color(foreground)),
+                    background: kept(attrs.background).or(color(background)),
+                    underline: kept(attrs.underline_color)
+                        .or(color(underline)),
+                }
+            }
+            Mode::Downsample(n) => {
+                let color =
+                    |c: Option<Color>| c.map(|c| self.downsample(c, n));
+                Colors {
+                    foreground: color(attrs.foreground),
+                    background: color(attrs.background),
+                    // Terminals limited to 16 colors generally don't support
+                    // underline colors.
+                    underline: color(attrs.underline_color).filter(|_| n > 16),
+                }
+            }
+        }
+    }
+
+    fn begin_sgr(&self) -> SgrProgress {
+        SgrProgress {
+            colors: self.parent_colors(),
+            reversed: self.parent_video_reversed(),
+            style: self.parent_style(),
+            any_written: false,
+            pending: Pending::None,
+            saved: self.attrs,
+            deferred: None,
+            params: Vec::new(),
+            params_truncated: false,
+        }
+    }
+
+    /// Handles the `;`-separated arguments in `params`.
+    fn handle_sgr_args(
+        &mut self,
+        progress: &mut SgrProgress,
+        params: &[u8],
+        write: &mut dyn FnMut(&[u8]),
+    ) {
+        for arg in params.split(|b| *b == b';') {
+            self.handle_sgr_arg(progress, arg, write);
+        }
+    }
+
+    fn handle_sgr_arg(
+        &mut self,
+        progress: &mut SgrProgress,
+        arg: &[u8],
+        write: &mut dyn FnMut(&[u8]),
+    ) {
+        // Arguments may contain colon-separated subparameters (ISO 8613-6);
+        // the first subparameter determines the attribute.
+        let mut subparams = arg.split(|b| *b == b':');
+        let n = subparams.next().and_then(parse_param);
+
+        match progress.pending {
+            Pending::None => {}
+            Pending::Format(target) => {
+                progress.pending = match n {
+                    Some(5) => Pending::Index(target),
+                    Some(2) => Pending::Rgb(target, [0; 3], 0),
+                    _ => Pending::None,
+                };
+                return;
+            }
+            Pending::Index(target) => {
+                progress.pending = Pending::None;
+                if let Some(i) = n.and_then(|n| u8::try_from(n).ok()) {
+                    let color = Some(Color::Indexed(i));
+                    self.set_color(target, color);
+                }
+                return;
+            }
+            Pending::Rgb(target, mut rgb, i) => {
+                rgb[i] = n.map_or(0, |n| n.min(255) as u8);
+                progress.pending = Pending::Rgb(target, rgb, i + 1);
+                if i == 2 {
+                    progress.pending = Pending::None;
+                    let color =
+                        Some(Color::Rgb(Rgb::new(rgb[0], rgb[1], rgb[2])));
+                    self.set_color(target, color);
+                }
+                return;
+            }
+        }
+
+        let basic = |n: u32, base: u32| Some(Color::Indexed((n - base) as u8));
+        let attrs = &mut self.attrs;
+        match n {
+            Some(0) => {
+                progress.reset();
+                *attrs = Attributes::DEFAULT;
+                progress.colors = Colors::DEFAULT;
+                progress.reversed = false;
+                progress.style = Style::DEFAULT;
+                progress.write_arg(b"0", write);
+            }
+            Some(1) => {
+                attrs.style.intensity = Intensity::High;
+            }
+            Some(2) => {
+                attrs.style.intensity = Intensity::Low;
+            }
+            Some(22) => {
+                attrs.style.intensity = Intensity::Normal;
+            }
+            Some(3) => {
+                attrs.style.italic = true;
+            }
+            Some(23) => {
+                attrs.style.italic = false;
+            }
+            Some(4) => {
+                let style = subparams.next().map_or(Some(1), parse_param);
+                if let Some(underline) = style.and_then(Underline::from_style)
+                {
+                    attrs.style.underline = underline;
+                }
+            }
+            Some(21) => {
+                attrs.style.underline = Underline::Double;
+            }
+            Some(24) => {
+                attrs.style.underline = Underline::None;
+            }
+            Some(5 | 6) => {
+                attrs.style.blink = true;
+            }
+            Some(25) => {
+                attrs.style.blink = false;
+            }
+            Some(9) => {
+                attrs.style.strikethrough = true;
+            }
+            Some(29) => {
+                attrs.style.strikethrough = false;
+            }
+            Some(53) => {
+                attrs.style.overline = true;
+            }
+            Some(55) => {
+                attrs.style.overline = false;
+            }
+            Some(7) => {
+                attrs.video_reversed = true;
+            }
+            Some(27) => {
+                attrs.video_reversed = false;
+            }
+            Some(n @ 30..=37) => {
+                let color = basic(n, 30);
+                self.set_color(Target::Foreground, color);
+            }
+            Some(n @ 90..=97) => {
+                let color = basic(n, 90 - 8);
+                self.set_color(Target::Foreground, color);
+            }
+            Some(n @ 40..=47) => {
+                let color = basic(n, 40);
+                self.set_color(Target::Background, color);
+            }
+            Some(n @ 100..=107) => {
+                let color = basic(n, 100 - 8);
+                self.set_color(Target::Background, color);
+            }
+            Some(n @ (38 | 48 | 58)) => {
+                let target = match n {
+                    38 => Target::Foreground,
+                    48 => Target::Background,
+                    _ => Target::Underline,
+                };
+                if !arg.contains(&b':') {
+                    progress.pending = Pending::Format(target);
+                } else if let Some(color) = parse_colon_color(subparams) {
+                    self.set_color(target, Some(color));
+                }
+            }
+            Some(39) => {
+                self.set_color(Target::Foreground, None);
+            }
+            Some(49) => {
+                self.set_color(Target::Background, None);
+            }
+            Some(59) => {
+                self.set_color(Target::Underline, None);
+            }
+            _ => {
+                progress.write_arg(arg, write);
+            }
+        }
+    }
+
+    /// Sets the color of `target` to `color`, or to the default color if
+    /// `color` is `None`.
+    fn set_color(&mut self, target: Target, color: Option<Color>) {
+        *match target {
+            Target::Foreground => &mut self.attrs.foreground,
+            Target::Background => &mut self.attrs.background,
+            Target::Underline => &mut self.attrs.underline_color,
+        } = color;
+    }
+
+    /// Returns the SGR argument that sets `target` to `color` (or to the
+    /// default color).
+    fn color_arg(target: Target, color: Option<Color>) -> String {
+        let base = match target {
+            Target::Foreground => 30,
+            Target::Background => 40,
+            Target::Underline => 50,
+        };
+        // Use the basic forms where possible, for terminals that don't
+        // support 256 colors. (Underline colors have no basic forms.)
+        let basic = !matches!(target, Target::Underline);
+        match color {
+            None => (base + 9).to_string(),
+            Some(Color::Indexed(i @ 0..=7)) if basic => {
+                (base + u32::from(i)).to_string()
+            }
+            Some(Color::Indexed(i @ 8..=15)) if basic => {
+                (base + 60 + u32::from(i - 8)).to_string()
+            }
+            Some(Color::Indexed(i)) => format!("{};5;{i}", base + 8),
+            Some(Color::Rgb(Rgb {
+                r,
+                g,
+                b,
+            })) => format!("{};2;{r};{g};{b}", base + 8),
+        }
+    }
+
+    /// Writes the arguments that update the parent terminal's colors, video
+    /// reversal, and style, followed by `final_bytes` if any arguments
+    /// have been written.
+    fn end_sgr(
+        &mut self,
+        mut progress: SgrProgress,
+        final_bytes: &[u8],
+        write: &mut dyn FnMut(&[u8]),
+    ) {
+        let old = progress.colors;
+        let new = self.parent_colors();
+        for (target, old, new) in [
+            (Target::Foreground, old.foreground, new.foreground),
+            (Target::Background, old.background, new.background),
+            (Target::Underline, old.underline, new.underline),
+        ] {
+            if new != old {
+                progress
+                    .write_arg(Self::color_arg(target, new).as_bytes(), write);
+            }
+        }
+
+        let new_reversed = self.parent_video_reversed();
+        if new_reversed != progress.reversed {
+            progress.write_arg(
+                if new_reversed {
+                    b"7"
+                } else {
+                    b"27"
+                },
+                write,
+            );
+        }
+
+        let old = progress.style;
+        let new = self.parent_style();
+        if new.intensity != old.intensity {
+            progress.write_arg(
+                match new.intensity {
+                    Intensity::High => b"1",
+                    Intensity::Low => b"2",
+                    Intensity::Normal => b"22",
+                },
+                write,
+            );
+        }
+        for (old, new, on, off) in [
+            (old.italic, new.italic, &b"3"[..], &b"23"[..]),
+            (old.blink, new.blink, b"5", b"25"),
+            (old.strikethrough, new.strikethrough, b"9", b"29"),
+            (old.overline, new.overline, b"53", b"55"),
+        ] {
+            if new != old {
+                progress.write_arg(
+                    if new {
+                        on
+                    } else {
+                        off
+                    },
+                    write,
+                );
+            }
+        }
+        if new.underline != old.underline {
+            progress.write_arg(new.underline.sgr_arg(), write);
+        }
+
+        if progress.any_written {
+            write(final_bytes);
+        }
+    }
+
+    /// Converts the SGR arguments in `params` as if they were applied to the
+    /// default rendition. Returns the resulting sequence without its final
+    /// byte (i.e., `CSI` followed by arguments), or nothing if the sequence
+    /// would have no effect.
+    fn translate_from_default(&self, params: &[u8]) -> Vec<u8> {
+        let mut scratch = SgrFilter {
+            settings: self.settings.clone(),
+            enabled: self.enabled,
+            attrs: Attributes::DEFAULT,
+            progress: None,
+        };
+        let mut progress = scratch.begin_sgr();
+        let mut args = Vec::new();
+        let mut push = |b: &[u8]| args.extend_from_slice(b);
+        scratch.handle_sgr_args(&mut progress, params, &mut push);
+        scratch.end_sgr(progress, b"", &mut push);
+        args
+    }
+
+    /// Handles DECCARA (`CSI Pt;Pl;Pb;Pr;Ps... $ r`) and DECRARA
+    /// (`CSI Pt;Pl;Pb;Pr;Ps... $ t`), which change or reverse the attributes
+    /// of a rectangular area.
+    fn handle_rect_attrs(
+        &mut self,
+        csi: &Csi<'_>,
+        write: &mut dyn FnMut(&[u8]),
+    ) {
+        // The attributes follow the four rectangle coordinates.
+        let Some(split) = csi
+            .params
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| **b == b';')
+            .nth(3)
+            .map(|(i, _)| i + 1)
+        else {
+            // No attributes are specified (or the sequence is malformed).
+            return write(csi.raw);
+        };
+        let (rect, attrs) = csi.params.split_at(split);
+
+        // The attributes apply to cells whose current rendition is unknown,
+        // so translate them as if each cell had the default rendition.
+        let args = self.translate_from_default(attrs);
+
+        // If no attributes remain (e.g., the sequence only set colors), the
+        // sequence has no effect. (Sending it without attributes would reset
+        // all attributes in the area.)
+        let Some(args) = args.strip_prefix(b"\x1b[") else {
+            return;
+        };
+        write(csi.controls);
+        write(b"\x1b[");
+        write(rect);
+        write(args);
+        write(csi.intermediates);
+        write(&[csi.final_byte]);
+    }
+
+    /// Ends a control sequence whose parameters have been streamed.
+    fn end_streamed(
+        &mut self,
+        mut progress: SgrProgress,
+        csi: &Csi<'_>,
+        write: &mut dyn FnMut(&[u8]),
+    ) {
+        if csi.is_sgr() {
+            progress.write_deferred(write);
+            self.handle_sgr_args(&mut progress, csi.params, write);
+            return self.end_sgr(progress, b"m", write);
+        }
+
+        // The sequence isn't SGR, so undo its effect on the attributes and
+        // forward it. Its start is gone, so it's rebuilt from the parameters.
+        self.attrs = progress.saved;
+        let mut params = progress.params;
+        if progress.params_truncated {
+            params.pop();
+        } else {
+            params.extend_from_slice(csi.params);
+        }
+        let mut raw = b"\x1b[".to_vec();
+        raw.extend_from_slice(&params);
+        raw.extend_from_slice(csi.intermediates);
+        raw.push(csi.final_byte);
+        let csi = Csi {
+            params: &params,
+            raw: &raw,
+            ..*csi
+        };
+        if is_rect_attrs(&csi) {
+            self.handle_rect_attrs(&csi, write);
+        } else {
+            write(csi.raw);
+        }
+    }
+
+    fn csi_dispatch(&mut self, csi: &Csi<'_>, write: &mut dyn FnMut(&[u8])) {
+        if let Some(progress) = self.progress.take() {
+            return self.end_streamed(progress, csi, write);
+        }
+        if is_rect_attrs(csi) {
+            return self.handle_rect_attrs(csi, write);
+        }
+        if !csi.is_sgr() {
+            return write(csi.raw);
+        }
+        write(csi.controls);
+        let mut progress = self.begin_sgr();
+        self.handle_sgr_args(&mut progress, csi.params, write);
+        self.end_sgr(progress, b"m", write);
+    }
+
+    fn csi_stream(
+        &mut self,
+        params: &[u8],
+        controls: &[u8],
+        write: &mut dyn FnMut(&[u8]),
+    ) {
+        write(controls);
+        let mut progress =
+            self.progress.take().unwrap_or_else(|| SgrProgress {
+                deferred: Some(Vec::new()),
+                ..self.begin_sgr()
+            });
+        progress.push_params(params);
+        if let Some(params) = params.strip_suffix(b";") {
+            self.handle_sgr_args(&mut progress, params, write);
+        }
+        self.progress = Some(progress);
+    }
+
+    fn csi_cancel(&mut self, _write: &mut dyn FnMut(&[u8])) {
+        // Nothing has been written for the sequence.
+        if let Some(progress) = self.progress.take() {
+            self.attrs = progress.saved;
+        }
+    }
+}
+
+/// Returns whether `csi` is DECCARA or DECRARA (see
+/// [`SgrFilter::handle_rect_attrs`]).
+fn is_rect_attrs(csi: &Csi<'_>) -> bool {
+    csi.private.is_none()
+        && csi.intermediates == b"$"
+        && matches!(csi.final_byte, b'r' | b't')
+}
+
+/// Handles the sequences that the parser passes to it.
+struct Handler {
+    sgr: SgrFilter,
+    osc: OscFilter,
+}
+
+impl Perform for Handler {
+    fn csi_dispatch(&mut self, csi: &Csi<'_>, write: &mut dyn FnMut(&[u8])) {
+        self.sgr.csi_dispatch(csi, write);
+    }
+
+    fn csi_stream(
+        &mut self,
+        params: &[u8],
+        controls: &[u8],
+        write: &mut dyn FnMut(&[u8]),
+    ) {
+        self.sgr.csi_stream(params, controls, write);
+    }
+
+    fn csi_cancel(&mut self, write: &mut dyn FnMut(&[u8])) {
+        self.sgr.csi_cancel(write);
+    }
+
+    fn osc_start(&mut self, command: &[u8]) -> bool {
+        self.osc.start(command)
+    }
+
+    fn osc_field(&mut self, field: &[u8]) {
+        self.osc.field(field);
+    }
+
+    fn osc_end(&mut self, terminator: &[u8], write: &mut dyn FnMut(&[u8])) {
+        self.osc.end(terminator, write);
+    }
+
+    fn osc_cancel(&mut self) {
+        self.osc.cancel();
+    }
+}
+
+/// Handles the sequences in data from the parent terminal, converting the
+/// colors in its replies to color queries to grays.
+struct ReplyHandler {
+    osc: OscFilter,
+    /// Whether a control sequence is being streamed.
+    streamed: bool,
+}
+
+impl Perform for ReplyHandler {
+    fn csi_dispatch(&mut self, csi: &Csi<'_>, write: &mut dyn FnMut(&[u8])) {
+        if !mem::take(&mut self.streamed) {
+            return write(csi.raw);
+        }
+        write(csi.params);
+        write(csi.intermediates);
+        write(&[csi.final_byte]);
+    }
+
+    fn csi_stream(
+        &mut self,
+        params: &[u8],
+        controls: &[u8],
+        write: &mut dyn FnMut(&[u8]),
+    ) {
+        write(controls);
+        if !mem::replace(&mut self.streamed, true) {
+            write(b"\x1b[");
+        }
+        write(params);
+    }
+
+    fn csi_cancel(&mut self, _write: &mut dyn FnMut(&[u8])) {
+        self.streamed = false;
+    }
+
+    fn osc_start(&mut self, command: &[u8]) -> bool {
+        self.osc.start(command)
+    }
+
+    fn osc_field(&mut self, field: &[u8]) {
+        self.osc.field(field);
+    }
+
+    fn osc_end(&mut self, terminator: &[u8], write: &mut dyn FnMut(&[u8])) {
+        self.osc.end(terminator, write);
+    }
+
+    fn osc_cancel(&mut self) {
+        self.osc.cancel();
+    }
+}
+
+/// How colors set with SGR are converted.
+#[derive(Clone, Copy, Default, Eq, PartialEq)]
+pub enum Mode {
+    /// Colors are removed; background colors become reverse video, and
+    /// foreground colors optionally become bold text.
+    #[default]
+    Mono,
+    /// Colors are converted to grays of the same luminance.
+    Grayscale,
+    /// Colors are converted to the nearest of the first 16 or 256 (the
+    /// contained value) palette colors.
+    Downsample(u16),
+}
+
+impl fmt::Display for Mode {
+    /// Formats the mode as `--mode` accepts it.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mono => f.write_str("mono"),
+            Self::Grayscale => f.write_str("grayscale"),
+            Self::Downsample(n) => write!(f, "{n}"),
+        }
+    }
+}
+
+/// Settings for converting SGR sequences.
+#[derive(Clone)]
+pub struct SgrSettings {
+    pub mode: Mode,
+    /// Convert foreground colors to bold text (in mono mode).
+    pub bold_colors: bool,
+    /// Convert foreground colors to faint, normal, or bold text according to
+    /// their contrast (in mono mode), instead of using `bold_colors`.
+    pub emphasis: bool,
+    /// The styles that replace foreground colors (in mono mode), taking
+    /// precedence over `bold_colors` and `emphasis`.
+    pub color_map: ColorMap,
+    /// Colors that are forwarded unchanged (in mono and grayscale modes),
+    /// along with colors close to them.
+    pub keep: ColorSet,
+    /// Emit truecolor grays instead of grays from the 256-color palette (in
+    /// grayscale mode).
+    pub truecolor_grays: bool,
+    /// Snap grays to these levels (in grayscale mode).
+    pub levels: Option<Levels>,
+    pub palette: Palette,
+}
+
+impl SgrSettings {
+    /// Converts the SGR parameters in `params`, e.g., `01;34`, as they would
+    /// be converted in mono mode when applied to the default rendition.
+    /// Returns the resulting parameters, which are empty if the sequence
+    /// would have no effect.
+    pub fn mono_sgr(&self, params: &str) -> String {
+        let sgr = SgrFilter {
+            settings: SgrSettings {
+                mode: Mode::Mono,
+                ..self.clone()
+            },
+            enabled: true,
+            attrs: Attributes::DEFAULT,
+            progress: None,
+        };
+        let args = sgr.translate_from_default(params.as_bytes());
+        let args = args.strip_prefix(b"\x1b[").unwrap_or_default();
+        String::from_utf8_lossy(args).into_owned()
+    }
+}
+
+/// Settings for a [`Filter`].
+pub struct Settings {
+    pub sgr: SgrSettings,
+    pub c1_mode: C1Mode,
+    /// What to do with OSCs that change colors.
+    pub osc_actions: OscActions,
+    /// Convert the colors in the parent terminal's replies to color queries
+    /// to grays.
+    pub gray_replies: bool,
+    /// The key chord that toggles filtering.
+    pub toggle_key: Option<Chord>,
+}
+
+/// The state of a running [`Filter`], as reported by [`Control::status`].
+struct Status {
+    enabled: bool,
+    settings: SgrSettings,
+    attrs: Attributes,
+}
+
+/// State shared between a [`Filter`] and its [`Control`] handles.
+struct Shared {
+    /// New settings that haven't been applied yet.
+    settings: Option<Settings>,
+    /// Whether filtering should be enabled, if requested and not yet
+    /// applied.
+    enabled: Option<bool>,
+    status: Status,
+}
+
+/// A handle for controlling a running [`Filter`], e.g., from another thread.
+/// Changes take effect between chunks of data from the child, once no
+/// sequence or character is in progress.
+#[derive(Clone)]
+pub struct Control(Arc<Mutex<Shared>>);
+
+impl Control {
+    /// Replaces the filter's settings.
+    pub fn send(&self, settings: Settings) {
+        self.0.lock().unwrap().settings = Some(settings);
+    }
+
+    /// Enables filtering, or disables it so that the child's output is
+    /// passed through unchanged.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.0.lock().unwrap().enabled = Some(enabled);
+    }
+
+    /// Switches between filtering and passing through the child's output.
+    pub fn toggle(&self) {
+        let mut shared = self.0.lock().unwrap();
+        let enabled = shared.enabled.unwrap_or(shared.status.enabled);
+        shared.enabled = Some(!enabled);
+    }
+
+    /// Describes the filter's settings and the graphic rendition requested
+    /// by the child, as `<name>: <value>` lines.
+    pub fn status(&self) -> String {
+        let shared = self.0.lock().unwrap();
+        let status = &shared.status;
+        let attrs = &status.attrs;
+        let on_off = |b| {
+            if b {
+                "on"
+            } else {
+                "off"
+            }
+        };
+        let color = |color: Option<Color>| {
+            color.map_or_else(|| "default".to_owned(), |c| c.to_string())
+        };
+        let levels = match &status.settings.levels {
+            Some(levels) => levels.to_string(),
+            None => "none".to_owned(),
+        };
+        let pending = shared.settings.is_some()
+            || shared.enabled.is_some_and(|e| e != status.enabled);
+        format!(
+            "filtering: {}\n\
+             mode: {}\n\
+             bold: {}\n\
+             emphasis: {}\n\
+             truecolor: {}\n\
+             levels: {levels}\n\
+             foreground: {}\n\
+             background: {}\n\
+             underline-color: {}\n\
+             reverse: {}\n\
+             style: {}\n\
+             pending: {}\n",
+            on_off(status.enabled),
+            status.settings.mode,
+            on_off(status.settings.bold_colors),
+            on_off(status.settings.emphasis),
+            on_off(status.settings.truecolor_grays),
+            color(attrs.foreground),
+            color(attrs.background),
+            color(attrs.underline_color),
+            on_off(attrs.video_reversed),
+            attrs.style,
+            if pending {
+                "yes"
+            } else {
+                "no"
+            },
+        )
+    }
+
+    fn take(&self) -> (Option<Settings>, Option<bool>) {
+        let mut shared = self.0.lock().unwrap();
+        (shared.settings.take(), shared.enabled.take())
+    }
+}
+
+/// Returns whether `data` ends with an incomplete UTF-8 character.
+fn ends_mid_char(data: &[u8]) -> bool {
+    for (i, b) in data.iter().rev().take(4).enumerate() {
+        // Skip continuation bytes to find the character's first byte.
+        if b & 0xc0 == 0x80 {
+            continue;
+        }
+        let len = match b {
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => 1,
+        };
+        return len > i + 1;
+    }
+    false
+}
+
+pub struct Filter {
+    parser: Parser,
+    handler: Handler,
+    /// Parses data from the parent terminal, if its replies are converted.
+    replies: Option<(Parser, ReplyHandler)>,
+    control: Control,
+    toggle_key: Option<Chord>,
+    /// Whether the last data from the child ended in the middle of a UTF-8
+    /// character.
+    mid_char: bool,
+}
+
+impl Filter {
+    pub fn new(settings: Settings) -> Self {
+        let status = Status {
+            enabled: true,
+            settings: settings.sgr.clone(),
+            attrs: Attributes::DEFAULT,
+        };
+        let control = Control(Arc::new(Mutex::new(Shared {
+            settings: None,
+            enabled: None,
+            status,
+        })));
+        Self {
+            parser: Parser::new(settings.c1_mode),
+            handler: Handler {
+                sgr: SgrFilter {
+                    settings: settings.sgr,
+                    enabled: true,
+                    attrs: Attributes::DEFAULT,
+                    progress: None,
+                },
+                osc: OscFilter::new(settings.osc_actions),
+            },
+            replies: settings
+                .gray_replies
+                .then(|| Self::reply_parser(settings.c1_mode)),
+            control,
+            toggle_key: settings.toggle_key,
+            mid_char: false,
+        }
+    }
+
+    /// Enables or disables filtering, writing the SGR sequence (if any) that
+    /// updates the parent terminal's rendition accordingly.
+    fn set_enabled(&mut self, enabled: bool, write: &mut dyn FnMut(&[u8])) {
+        let sgr = &mut self.handler.sgr;
+        let progress = sgr.begin_sgr();
+        sgr.enabled = enabled;
+        sgr.end_sgr(progress, b"m", write);
+    }
+
+    /// Records the parts of the filter's state that change as data is
+    /// filtered, for [`Control::status`].
+    fn update_status(&self) {
+        let sgr = &self.handler.sgr;
+        let status = &mut self.control.0.lock().unwrap().status;
+        status.enabled = sgr.enabled;
+        status.attrs = sgr.attrs;
+    }
+
+    fn reply_parser(c1_mode: C1Mode) -> (Parser, ReplyHandler) {
+        (
+            Parser::new(c1_mode),
+            ReplyHandler {
+                osc: OscFilter::new(OscActions::new(OscAction::Gray)),
+                streamed: false,
+            },
+        )
+    }
+
+    /// Returns a handle for controlling the filter.
+    pub fn control(&self) -> Control {
+        self.control.clone()
+    }
+
+    /// Replaces the filter's settings, writing the SGR sequence (if any) that
+    /// updates the parent terminal's rendition to match the child's current
+    /// attributes under the new settings.
+    fn apply(&mut self, settings: Settings, write: &mut dyn FnMut(&[u8])) {
+        self.control.0.lock().unwrap().status.settings = settings.sgr.clone();
+        let sgr = &mut self.handler.sgr;
+        let progress = sgr.begin_sgr();
+        sgr.settings = settings.sgr;
+        sgr.end_sgr(progress, b"m", write);
+
+        self.parser.set_c1_mode(settings.c1_mode);
+        self.handler.osc.set_actions(settings.osc_actions);
+        self.toggle_key = settings.toggle_key;
+        match &mut self.replies {
+            None if settings.gray_replies => {
+                self.replies = Some(Self::reply_parser(settings.c1_mode));
+            }
+            // A reply that is still being received continues to be
+            // converted; the parser is dropped at the next change.
+            Some((parser, _)) if parser.is_intercepting_osc() => {}
+            Some(_) if !settings.gray_replies => self.replies = None,
+            Some((parser, _)) if parser.is_ground() => {
+                parser.set_c1_mode(settings.c1_mode);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl filterm::Filter for Filter {
+    fn on_child_data<F>(&mut self, data: &[u8], mut parent_write: F)
+    where
+        F: FnMut(&[u8]),
+    {
+        if self.parser.is_ground() && !self.mid_char {
+            let (settings, enabled) = self.control.take();
+            if let Some(settings) = settings {
+                self.apply(settings, &mut parent_write);
+            }
+            if let Some(enabled) = enabled {
+                self.set_enabled(enabled, &mut parent_write);
+            }
+        }
+        if self.handler.sgr.enabled {
+            data.iter().copied().for_each(|b| {
+                self.parser.advance(b, &mut self.handler, &mut parent_write);
+            });
+        } else {
+            // The data is still parsed to track the child's attributes.
+            data.iter().copied().for_each(|b| {
+                self.parser.advance(b, &mut self.handler, &mut |_| {});
+            });
+            parent_write(data);
+        }
+        if !data.is_empty() {
+            self.mid_char = ends_mid_char(data);
+        }
+        self.update_status();
+    }
+
+    fn on_parent_data<F>(&mut self, data: &[u8], mut child_write: F)
+    where
+        F: FnMut(&[u8]),
+    {
+        let data = match &self.toggle_key {
+            Some(key) => {
+                let (data, count) = key.strip(data);
+                if count % 2 == 1 {
+                    self.control.toggle();
+                }
+                data
+            }
+            None => Cow::Borrowed(data),
+        };
+        let data = &data[..];
+        let Some((parser, handler)) = &mut self.replies else {
+            return child_write(data);
+        };
+        data.iter().copied().for_each(|b| {
+            parser.advance(b, handler, &mut child_write);
+        });
+        // Other than replies, data from the parent is mostly keyboard input,
+        // which shouldn't be delayed (e.g., a lone ESC key press). A reply
+        // is held back only once its header (`ESC ] <digits> ;`) has arrived
+        // in a single chunk, which key presses don't do.
+        if !parser.is_intercepting_osc() {
+            parser.flush(handler, &mut child_write);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use filterm::Filter as _;
+
+    fn settings(mode: Mode) -> Settings {
+        Settings {
+            sgr: SgrSettings {
+                mode,
+                bold_colors: false,
+                emphasis: false,
+                color_map: ColorMap::default(),
+                keep: ColorSet::default(),
+                truecolor_grays: false,
+                levels: None,
+                palette: Palette::default(),
+            },
+            c1_mode: C1Mode::Disabled,
+            osc_actions: OscActions::default(),
+            gray_replies: false,
+            toggle_key: None,
+        }
+    }
+
+    fn bold() -> Settings {
+        let mut settings = settings(Mode::Mono);
+        settings.sgr.bold_colors = true;
+        settings
+    }
+
+    /// Filters `input` from the child, split into two chunks at every
+    /// possible position, and checks that the output is `expected` each
+    /// time.
+    fn check_with(
+        settings: impl Fn() -> Settings,
+        input: &[u8],
+        expected: &[u8],
+    ) {
+        for split in 0..=input.len() {
+            let mut filter = Filter::new(settings());
+            let mut output = Vec::new();
+            for chunk in [&input[..split], &input[split..]] {
+                filter.on_child_data(chunk, |b| output.extend_from_slice(b));
+            }
+            assert_eq!(
+                String::from_utf8_lossy(&output),
+                String::from_utf8_lossy(expected),
+                "split at {split}",
+            );
+        }
+    }
+
+    fn check(input: &[u8], expected: &[u8]) {
+        check_with(|| settings(Mode::Mono), input, expected);
+    }
+
+    #[test]
+    fn mono() {
+        check(b"\x1b[31mx\x1b[0m", b"x\x1b[0m");
+        check(b"\x1b[1;30;47mx\x1b[39;49m", b"\x1b[7;1mx\x1b[27m");
+        check_with(bold, b"\x1b[31mx\x1b[39m", b"\x1b[1mx\x1b[22m");
+    }
+
+    #[test]
+    fn underline() {
+        check(
+            b"\x1b[4:2mx\x1b[21my\x1b[4:3mz\x1b[4:0m",
+            b"\x1b[21mxy\x1b[4:3mz\x1b[24m",
+        );
+        let map = || {
+            let mut settings = settings(Mode::Mono);
+            settings.sgr.color_map =
+                ColorMap::parse("red=double-underline").unwrap();
+            settings
+        };
+        check_with(map, b"\x1b[31mx\x1b[39m", b"\x1b[21mx\x1b[24m");
+    }
+
+    #[test]
+    fn double_escape() {
+        check(b"\x1b\x1b[31mx", b"x");
+        check_with(bold, b"\x1b\x1b[31mx", b"\x1b[1mx");
+    }
+
+    #[test]
+    fn escape_in_csi() {
+        check(b"\x1b[3\x1b[31mx", b"x");
+        check_with(bold, b"\x1b[1;\x1b[31mx", b"\x1b[1mx");
+    }
+
+    #[test]
+    fn other_sequences() {
+        for input in [
+            &b"\x1b[2J\x1b[1;1H\x1b[?25l\x1b[>4;1m\x1b[ q\x1b(B\x1b7"[..],
+            b"\x1b[x\x1b[1:2:3:4:5:6:7:8:9:10x\x1b[31\x18m",
+        ] {
+            check(input, input);
+        }
+    }
+
+    #[test]
+    fn colors_in_strings() {
+        // Sixel color registers are data, not colors set with SGR.
+        check(
+            b"\x1bPq#1;2;100;0;0#1~~\x1b\\",
+            b"\x1bPq#1;2;100;0;0#1~~\x1b\\",
+        );
+        check(b"\x1b]0;\x9b31m\x07", b"\x1b]0;\x9b31m\x07");
+        check(b"\x1b_[31m\x1b\\", b"\x1b_[31m\x1b\\");
+        // An ESC ends a control string, so the string is ended with ST
+        // before any replacement for the sequence that follows.
+        check(b"\x1bPq#1\x1b[31mx", b"\x1bPq#1\x1b\\x");
+        check(b"\x1b]0;t\x1b[31mx", b"\x1b]0;t\x1b\\x");
+        check(b"\x1b]0;t\x1b[41mx", b"\x1b]0;t\x1b\\\x1b[7mx");
+        check(b"\x1b_t\x1b[41mx", b"\x1b_t\x1b\\\x1b[7mx");
+    }
+
+    #[test]
+    fn tmux_passthrough() {
+        check(b"\x1bPtmux;\x1b\x1b[31mx\x1b\\", b"\x1bPtmux;x\x1b\\");
+        check(
+            b"\x1bPtmux;\x1b\x1b[41mx\x1b\x1b[0m\x1b\\\x1b[41m",
+            b"\x1bPtmux;\x1b\x1b[7mx\x1b\x1b[0m\x1b\\\x1b[7m",
+        );
+        check(
+            b"\x1bPtmux;\x1b\x1b]0;t\x07\x1b\x1b[?25l\x1b\\",
+            b"\x1bPtmux;\x1b\x1b]0;t\x07\x1b\x1b[?25l\x1b\\",
+        );
+    }
+
+    #[test]
+    fn long_sgr() {
+        let resets = "0;".repeat(100);
+        let bold = "1;".repeat(100);
+        let input = format!("\x1b[{resets}8;31;41;{resets}{bold}30;47mx");
+        check(input.as_bytes(), b"\x1b[0;7;1mx");
+        let input = format!("\x1b[{resets}8;\r{bold}30;47m");
+        check(input.as_bytes(), b"\r\x1b[0;8;7;1m");
+        let input = format!("\x1b[{resets}31\x18m\x1b[4m");
+        check(input.as_bytes(), b"\x18m\x1b[4m");
+    }
+
+    #[test]
+    fn long_other_sequences() {
+        let input = format!("\x1b[{}1Hx", "1;".repeat(80));
+        check(input.as_bytes(), input.as_bytes());
+        let input = format!("\x1b[{}1 qx", "1;".repeat(80));
+        check(input.as_bytes(), input.as_bytes());
+        // Parameters beyond the limit are dropped.
+        let input = format!("\x1b[{}1H", "01;".repeat(1000));
+        let mut filter = Filter::new(settings(Mode::Mono));
+        let mut output = Vec::new();
+        filter.on_child_data(input.as_bytes(), |b| output.extend(b));
+        let params = output.strip_prefix(b"\x1b[").unwrap();
+        let params = params.strip_suffix(b"H").unwrap();
+        assert!(params.len() <= STREAMED_PARAMS_MAX_LEN);
+        assert!(params.len() > STREAMED_PARAMS_MAX_LEN / 2);
+        assert!(params.split(|b| *b == b';').all(|p| p == b"1"));
+    }
+
+    #[test]
+    fn long_rect_attrs() {
+        let attrs = "4;".repeat(80);
+        let input = format!("\x1b[1;1;5;5;{attrs}30;47$r");
+        check(input.as_bytes(), b"\x1b[1;1;5;5;7;4$r");
+        let input = format!("\x1b[1;1;5;5;{attrs}32$r");
+        check(input.as_bytes(), b"\x1b[1;1;5;5;4$r");
+    }
+
+    /// Filters `chunks` of input from the parent, with replies converted,
+    /// and checks that the output is `expected`.
+    fn check_input(chunks: &[&[u8]], expected: &[u8]) {
+        let mut filter = Filter::new(Settings {
+            gray_replies: true,
+            ..settings(Mode::Mono)
+        });
+        let mut output = Vec::new();
+        for chunk in chunks {
+            filter.on_parent_data(chunk, |b| output.extend_from_slice(b));
+        }
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            String::from_utf8_lossy(expected),
+        );
+    }
+
+    #[test]
+    fn parent_input() {
+        check_input(&[b"\x1b", b"]", b"x"], b"\x1b]x");
+        check_input(&[b"\x1b]", b"4;x"], b"\x1b]4;x");
+        check_input(&[b"\x1b]4", b";1;x"], b"\x1b]4;1;x");
+        check_input(&[b"\x1b]0;x"], b"\x1b]0;x");
+        check_input(
+            &[b"\x1b]11;rgb:ffff/0000/0000", b"\x1b", b"\\x"],
+            b"\x1b]11;rgb:7f7f/7f7f/7f7f\x1b\\x",
+        );
+    }
+
+    #[test]
+    fn levels() {
+        let levels = || {
+            let mut settings = settings(Mode::Grayscale);
+            settings.sgr.levels = Some(Levels::evenly_spaced(2));
+            settings
+        };
+        // The default (xterm) palette has a black background and a white
+        // foreground.
+        check_with(levels, b"\x1b[38;5;236mx", b"\x1b[38;5;231mx");
+        check_with(levels, b"\x1b[48;5;252mx", b"\x1b[48;5;16mx");
+        check_with(
+            levels,
+            b"\x1b[38;5;236;48;5;238mx",
+            b"\x1b[38;5;231;48;5;16mx",
+        );
+        check_with(
+            levels,
+            b"\x1b[38;5;231;48;5;16mx",
+            b"\x1b[38;5;231;48;5;16mx",
+        );
+    }
+
+    #[test]
+    fn palette_levels() {
+        // 100 and 102 are both shown as palette gray 241 (#626262), so
+        // only one of them can keep colors apart.
+        let palette = Palette::default();
+        let levels = Levels::parse("100,102,200").unwrap();
+        let levels = levels.dedup_by_key(|l| palette.nearest_gray(l));
+        assert_eq!(levels.as_ref().map(Levels::to_string).unwrap(), "100,200");
+        let levels = || {
+            let mut settings = settings(Mode::Grayscale);
+            settings.sgr.levels = levels.clone();
+            settings
+        };
+        check_with(
+            levels,
+            b"\x1b[38;5;244;48;5;245mx",
+            b"\x1b[38;5;251;48;5;241mx",
+        );
+        let levels = Levels::parse("100,101").unwrap();
+        assert!(levels.dedup_by_key(|l| palette.nearest_gray(l)).is_none());
+    }
+
+    #[test]
+    fn grayscale() {
+        check_with(
+            || settings(Mode::Grayscale),
+            b"\x1b[38;2;255;255;255mx",
+            b"\x1b[38;5;231mx",
+        );
+    }
+
+    #[test]
+    fn downsample() {
+        let colors = |n| move || settings(Mode::Downsample(n));
+        check_with(colors(16), b"\x1b[38;2;250;0;0mx", b"\x1b[91mx");
+        check_with(colors(16), b"\x1b[38;5;1;48;5;196mx", b"\x1b[31;101mx");
+        check_with(colors(16), b"\x1b[31;58;5;1mx", b"\x1b[31mx");
+        check_with(colors(256), b"\x1b[38;2;250;0;0mx", b"\x1b[91mx");
+        check_with(
+            colors(256),
+            b"\x1b[38;5;100;58:5:1mx",
+            b"\x1b[38;5;100;58;5;1mx",
+        );
+        check_with(colors(256), b"\x1b[48;2;0;95;135mx", b"\x1b[48;5;24mx");
+    }
+}
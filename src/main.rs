@@ -1,5 +1,5 @@
 /*
- * Copyright (C) 2021-2022, 2024 taylor.fish <contact@taylor.fish>
+ * Copyright (C) 2021-2022, 2024, 2026 taylor.fish <contact@taylor.fish>
  *
  * This file is part of Monoterm.
  *
@@ -17,10 +17,40 @@
  * along with Monoterm. If not, see <https://www.gnu.org/licenses/>.
  */
 
+use nix::sys::signal::Signal;
 use std::env;
-use std::ffi::{OsStr, OsString};
+use std::ffi::OsString;
+use std::fs;
 use std::mem;
+use std::path::PathBuf;
 use std::process::exit;
+use std::sync::{Arc, Mutex};
+
+mod chord;
+mod color;
+mod config;
+mod control;
+mod environment;
+mod filter;
+mod osc;
+mod palette;
+mod parser;
+mod probe;
+mod signals;
+mod style;
+mod terminfo;
+mod tool_colors;
+mod watch;
+
+use chord::Chord;
+use color::{Levels, Rgb};
+use config::Config;
+use environment::EnvChanges;
+use filter::{Control, Filter, Mode, Settings, SgrSettings};
+use osc::{OscAction, OscActions};
+use palette::Palette;
+use parser::C1Mode;
+use style::{ColorMap, ColorSet};
 
 const USAGE: &str = "\
 Usage: monoterm [options] <command> [args...]
@@ -28,234 +58,158 @@ Usage: monoterm [options] <command> [args...]
 Executes <command> while converting all terminal colors to monochrome.
 
 Options:
-  -b, --bold     Convert foreground colors to bold text
-  -h, --help     Show this help message
-  -v, --version  Show program version
-";
+  -b, --bold                 Convert foreground colors to bold text
+  -c, --c1-controls          Recognize 8-bit (C1) control characters
+  -e, --emphasis             Convert foreground colors to faint, normal, or
+                             bold text according to their contrast
+      --foreground <color>   Default foreground color (see below)
+      --background <color>   Default background color (see below)
+      --mode <mode>          Color mode: mono, grayscale, 16, or 256
+  -g, --grayscale            Convert colors to grays instead of removing them
+  -n, --colors <n>           Convert colors to the nearest of 16 or 256 colors
+  -l, --levels <levels>      With --grayscale, snap grays to these levels
+  -m, --map <rules>          Convert foreground colors to these text styles
+  -k, --keep <colors>        Keep these colors instead of removing them
+  -t, --truecolor            With --grayscale, use truecolor grays
+  -p, --palette <file>       Load the terminal's palette from a theme file
+  -q, --probe                Query the terminal for its palette at startup
+  -o, --osc-colors <spec>    How to handle palette and dynamic color changes
+  -r, --gray-replies         Report grays in replies to color queries
+      --toggle-key <key>     Turn filtering off and on when <key> is pressed
+  -N, --no-color             Tell <command> to avoid colors (see below)
+      --no-color-env <vars>  Environment changes made by --no-color
+      --tool-colors          Make ls, grep, gcc, and git use text attributes
+      --terminfo             Give <command> a terminfo entry without colors
+      --config <file>        Read settings from this configuration file
+      --profile <name>       Use this profile from the configuration file
+      --control-socket <path>
+                             Accept commands on a Unix socket at <path>
+  -h, --help                 Show this help message
+  -v, --version              Show program version
 
-/// Maximum length of a single SGR sequence, excluding the initial CSI and
-/// the ending 'm'. Sequences longer than this length will be forwarded to the
-/// parent terminal unmodified.
-const SGR_MAX_LEN: usize = 128;
+In mono mode, a background color is shown as reverse video only if it
+contrasts with the terminal's default background and, when a foreground color
+is also set, only if the pair is dark on light (or light on dark if the
+default colors are). With --emphasis, colored text with low contrast becomes
+faint, and text with high contrast becomes bold; gray text becomes faint if
+it's dimmer than the default foreground. Grays outside the 16 basic colors are
+not converted to bold by --bold. The default colors are assumed to be white on
+black unless --foreground and --background specify them (e.g., \"#ffffff\" or
+\"rgb:ff/ff/ff\").
 
-enum SgrState {
-    Init,
-    AfterEsc,
-    AfterCsi,
-}
+Colors are judged using xterm's default palette unless --palette loads one from
+a theme file: Xresources (*color0: #rrggbb), Alacritty (TOML), kitty (color0
+#rrggbb), foot (INI), or a list of <index>=#rrggbb lines. Theme files can also
+set the default colors (foreground and background). With --probe, the colors
+reported by the terminal take precedence over the theme file; if the terminal
+doesn't reply, the theme file or xterm's palette is used.
 
-#[derive(Clone, Copy, Eq, PartialEq)]
-enum Intensity {
-    High,
-    Low,
-    Normal,
-}
+<rules> is a comma-separated list of rules of the form <color>=<style>, e.g.,
+\"red=underline,green=italic,bright*=bold\". <color> is a palette index (0-255)
+or the name of a basic color (black, red, green, yellow, blue, magenta, cyan,
+or white, optionally prefixed with \"bright-\"), in which '*' matches any
+characters. Other colors match the names of the basic colors closest to them.
+<style> is \"none\" or a '+'-separated list of attributes: bold, faint,
+italic, underline, double-underline, curly-underline, dotted-underline,
+dashed-underline, blink, strikethrough, or overline. Later rules take
+precedence, and colors that match no rule are handled according to --bold.
 
-struct Filter {
-    bold_colors: bool,
-    state: SgrState,
-    background_set: bool,
-    video_reversed: bool,
-    foreground_set: bool,
-    intensity: Intensity,
-    /// Stores the contents of possible in-progress SGR escape sequences.
-    buffer: Vec<u8>,
-}
+With --keep, colors listed in <colors> (separated by commas, and specified like
+<color> above) are forwarded unchanged, as are other colors close to them;
+e.g., \"--keep red,bright-red\" keeps red text for displays with one accent
+color. This applies in grayscale mode as well.
 
-impl Filter {
-    pub fn new(bold_colors: bool) -> Self {
-        Self {
-            bold_colors,
-            state: SgrState::Init,
-            background_set: false,
-            video_reversed: false,
-            foreground_set: false,
-            intensity: Intensity::Normal,
-            buffer: Vec::new(),
-        }
-    }
+With --grayscale, each foreground, background, and underline color is
+converted to the gray of the same luminance, chosen from the 256-color gray
+ramp (or set directly with --truecolor), and --bold has no effect.
 
-    fn parent_video_reversed(&self) -> bool {
-        self.background_set != self.video_reversed
-    }
+For displays that show only a few gray levels, --levels snaps each gray to the
+nearest level. <levels> is either a number of evenly spaced levels from black
+to white (e.g., \"4\") or a comma-separated list of levels from 0 to 255 (e.g.,
+\"0,96,192,255\"). A foreground and background that would land on the same
+level are kept on adjacent levels, including when one of them is the default
+color. Without --truecolor, levels shown as the same palette gray count as one.
+--levels implies --grayscale.
 
-    fn parent_intensity(&self) -> Intensity {
-        if self.intensity == Intensity::Normal
-            && self.bold_colors
-            && self.foreground_set
-        {
-            Intensity::High
-        } else {
-            self.intensity
-        }
-    }
+With --colors, colors are kept but limited to the first 16 or 256 colors of the
+palette, for terminals that can't show more: each other color is converted to
+the perceptually closest palette color, and --bold has no effect. 16 colors are
+set using only the basic SGR forms (30-37, 40-47, 90-97, and 100-107).
 
-    fn handle_sgr<F>(&mut self, mut write: F)
-    where
-        F: FnMut(&[u8]),
-    {
-        fn skip_38_48(mut iter: impl Iterator<Item = Option<u8>>) {
-            match iter.next() {
-                Some(Some(5)) => {
-                    iter.next();
-                }
-                Some(Some(2)) => {
-                    iter.next(); // r
-                    iter.next(); // g
-                    iter.next(); // b
-                }
-                _ => {}
-            }
-        }
+Palette and dynamic color changes (OSC 4, 10-19, and 21) can be kept,
+dropped, or converted to grays of the same luminance (the default, except with
+--colors, which keeps them). <spec> is a comma-separated list of actions
+(keep, drop, or gray), each optionally preceded by an OSC number and '=' to
+apply it to only that OSC; e.g., \"drop,4=gray\". Resets (OSC 104 and
+110-119) are kept unless dropped.
 
-        let mut iter = self.buffer.split(|b| *b == b';').map(|arg| {
-            (arg, match arg {
-                [] => Some(0),
-                _ => (|| std::str::from_utf8(arg).ok()?.parse().ok())(),
-            })
-        });
+With --gray-replies, when a program queries the terminal's colors (e.g., to
+detect a light or dark background), each color in the terminal's reply is
+converted to the gray of the same luminance.
 
-        let mut any_written = false;
-        let mut write_arg = |arg: &[u8]| {
-            write(if mem::replace(&mut any_written, true) {
-                b";"
-            } else {
-                b"\x1b["
-            });
-            write(arg);
-        };
+With --toggle-key, pressing <key> switches between filtering and passing the
+command's output through unchanged (e.g., to read a color-coded chart),
+starting with its next output. <key> is ctrl-<key>, alt-<key>, or
+ctrl-alt-<key>, e.g., \"ctrl-]\" or \"alt-m\", and is not sent to <command>.
 
-        let mut reversed = self.parent_video_reversed();
-        let mut intensity = self.parent_intensity();
-        while let Some((arg, n)) = iter.next() {
-            match n {
-                Some(0) => {
-                    self.background_set = false;
-                    self.video_reversed = false;
-                    self.foreground_set = false;
-                    self.intensity = Intensity::Normal;
-                    reversed = false;
-                    intensity = Intensity::Normal;
-                    write_arg(b"0");
-                }
-                Some(1) => {
-                    self.intensity = Intensity::High;
-                }
-                Some(2) => {
-                    self.intensity = Intensity::Low;
-                }
-                Some(22) => {
-                    self.intensity = Intensity::Normal;
-                }
-                Some(30..=37 | 90..=97) => {
-                    self.foreground_set = true;
-                }
-                Some(38) => {
-                    skip_38_48(iter.by_ref().map(|(_, n)| n));
-                    self.foreground_set = true;
-                }
-                Some(39) => {
-                    self.foreground_set = false;
-                }
-                Some(58 | 59) => {}
-                Some(7) => {
-                    self.video_reversed = true;
-                }
-                Some(27) => {
-                    self.video_reversed = false;
-                }
-                Some(40..=47) => {
-                    self.background_set = true;
-                }
-                Some(48) => {
-                    skip_38_48(iter.by_ref().map(|(_, n)| n));
-                    self.background_set = true;
-                }
-                Some(49) => {
-                    self.background_set = false;
-                }
-                Some(100..=107) => {
-                    self.background_set = true;
-                }
-                _ => {
-                    write_arg(arg);
-                }
-            }
-        }
+With --no-color, the environment of <command> is changed so that programs avoid
+colors in the first place: by default, NO_COLOR is set to 1, COLORTERM is
+removed, and COLORFGBG is set to \"15;0\" or \"0;15\" according to whether
+the default background is dark or light. <vars> is a comma-separated list of
+changes, each of which is <name>=<value>, -<name> (to remove a variable),
+NO_COLOR, or COLORFGBG; e.g., \"NO_COLOR,-COLORTERM,TERM=xterm-mono\" also
+sets TERM. --no-color-env implies --no-color.
 
-        let new_reversed = self.parent_video_reversed();
-        if new_reversed != reversed {
-            write_arg(if new_reversed {
-                b"7"
-            } else {
-                b"27"
-            });
-        }
+With --tool-colors, LS_COLORS, GREP_COLORS, GCC_COLORS, and Git's color
+settings (through GIT_CONFIG_PARAMETERS) are set for <command> to the text
+attributes that their colors are converted to in mono mode, using the values
+already in the environment or else the defaults. Without --map, ls shows
+directories in bold, symbolic links underlined, and executables in italics,
+grep shows matches in reverse video, and git diff shows removed lines faint and
+added lines in bold, even if filtering is turned off.
 
-        let new_intensity = self.parent_intensity();
-        if new_intensity != intensity {
-            write_arg(match new_intensity {
-                Intensity::High => b"1",
-                Intensity::Low => b"2",
-                Intensity::Normal => b"22",
-            });
-        }
+With --terminfo, a terminfo entry named monoterm-mono-<term>, where <term> is
+the value of TERM, is compiled with tic into ~/.terminfo (or $TERMINFO), and
+TERM is set to it for <command>. The entry is that of <term> without colors,
+so programs that check the terminal's capabilities (e.g., with \"tput
+colors\") don't send colors at all. In grayscale mode, the entry is named
+monoterm-gray-<term> and keeps colors, as it does in mono mode with --keep
+(named monoterm-mono-keep-<term>); with --colors, it's named
+monoterm-<n>-<term> and reports <n> colors. In every mode, capabilities for
+changing the palette and for truecolor are removed.
 
-        if any_written {
-            write(b"m");
-        }
-    }
+Settings are also read from $XDG_CONFIG_HOME/monoterm/config.toml (by default,
+~/.config/monoterm/config.toml) unless --config specifies another file. Its
+top-level keys are the long names of options (e.g., bold = true or keep =
+[\"red\", \"bright-red\"]), and [profiles.<name>] tables hold profiles that
+--profile selects. Each option can also be set with an environment variable
+named MONOTERM_ followed by the option's long name in uppercase with '-'
+replaced by '_' (e.g., MONOTERM_OSC_COLORS=drop); MONOTERM_PROFILE and
+MONOTERM_CONFIG select a profile and file. Options on the command line take
+precedence over the environment, which takes precedence over the profile and
+then the rest of the file. Options without values can be given \"true\" or
+\"false\" to turn them on or off.
 
-    fn handle_byte<F>(&mut self, b: u8, mut write: F)
-    where
-        F: FnMut(&[u8]),
-    {
-        match &self.state {
-            SgrState::Init => match b {
-                0x1b => {
-                    self.state = SgrState::AfterEsc;
-                }
-                b => write(&[b]),
-            },
-            SgrState::AfterEsc => match b {
-                b'[' => {
-                    self.state = SgrState::AfterCsi;
-                    self.buffer.clear();
-                }
-                b => {
-                    self.state = SgrState::Init;
-                    write(&[0x1b, b]);
-                }
-            },
-            SgrState::AfterCsi => match b {
-                b'm' => {
-                    self.state = SgrState::Init;
-                    self.handle_sgr(write);
-                }
-                b'0'..=b'9' | b';' if self.buffer.len() < SGR_MAX_LEN => {
-                    self.buffer.push(b);
-                }
-                b => {
-                    self.state = SgrState::Init;
-                    write(b"\x1b[");
-                    write(&self.buffer);
-                    write(&[b]);
-                }
-            },
-        }
-    }
-}
+Changes to the configuration file (including creating it, or changing the file
+it links to) take effect while <command> runs, starting with its next output.
+If the file becomes invalid, the current settings are kept. The terminal is
+probed, and the environment of <command> is changed, only at startup.
 
-impl filterm::Filter for Filter {
-    fn on_child_data<F>(&mut self, data: &[u8], mut parent_write: F)
-    where
-        F: FnMut(&[u8]),
-    {
-        data.iter().copied().for_each(|b| {
-            self.handle_byte(b, &mut parent_write);
-        });
-    }
-}
+While <command> runs, SIGUSR1 switches between filtering and passing its
+output through (like --toggle-key), and SIGUSR2 switches to the next profile
+in the configuration file, or back to the top-level settings after the last
+profile.
+
+With --control-socket, other programs can connect to <path> and send commands,
+one per line: \"status\" reports the settings and the command's current text
+attributes, \"set <option> <value>\" sets an option by its long name (e.g., \"set
+mode grayscale\" or \"set bold on\") until monoterm exits, \"pause\" and \"resume\"
+turn filtering off and on, and \"reload\" reloads the configuration file. Each
+reply ends with a line containing \"ok\" or \"error: <message>\". Options that
+take effect only at startup (--probe, --no-color, --no-color-env,
+--tool-colors, and --terminfo) can't be set.
+MONOTERM_CONTROL_SOCKET can be set instead of --control-socket.
+";
 
 fn show_usage() -> ! {
     print!("{USAGE}");
@@ -275,73 +229,614 @@ macro_rules! args_error {
     }};
 }
 
+/// Each option's short name, long name, and whether it takes a value.
+const OPTIONS: &[(Option<u8>, &str, bool)] = &[
+    (None, "background", true),
+    (Some(b'b'), "bold", false),
+    (Some(b'c'), "c1-controls", false),
+    (None, "config", true),
+    (None, "control-socket", true),
+    (Some(b'e'), "emphasis", false),
+    (None, "foreground", true),
+    (Some(b'g'), "grayscale", false),
+    (Some(b'n'), "colors", true),
+    (Some(b'h'), "help", false),
+    (Some(b'k'), "keep", true),
+    (Some(b'l'), "levels", true),
+    (Some(b'm'), "map", true),
+    (None, "mode", true),
+    (Some(b'N'), "no-color", false),
+    (None, "no-color-env", true),
+    (Some(b'o'), "osc-colors", true),
+    (Some(b'p'), "palette", true),
+    (None, "profile", true),
+    (Some(b'q'), "probe", false),
+    (Some(b'r'), "gray-replies", false),
+    (Some(b't'), "truecolor", false),
+    (None, "toggle-key", true),
+    (None, "terminfo", false),
+    (None, "tool-colors", false),
+    (Some(b'v'), "version", false),
+];
+
+/// Options that can be given only on the command line (or, except for `help`
+/// and `version`, in the environment).
+const COMMAND_LINE_ONLY: &[&str] =
+    &["config", "control-socket", "help", "profile", "version"];
+
+/// Options that take effect only at startup (they probe the terminal or
+/// change the environment of the command), so they can't be set while the
+/// command runs.
+const STARTUP_ONLY: &[&str] =
+    &["no-color", "no-color-env", "probe", "terminfo", "tool-colors"];
+
+/// Settings that can come from the command line, the configuration file, or
+/// the environment.
+#[derive(Clone, Default)]
+struct Options {
+    pub bold: bool,
+    pub c1_controls: bool,
+    pub mode: Mode,
+    pub truecolor: bool,
+    pub levels: Option<Levels>,
+    pub color_map: ColorMap,
+    pub keep: ColorSet,
+    pub emphasis: bool,
+    pub palette: Option<PathBuf>,
+    pub probe: bool,
+    pub foreground: Option<Rgb>,
+    pub background: Option<Rgb>,
+    pub osc_actions: OscActions,
+    pub gray_replies: bool,
+    pub toggle_key: Option<Chord>,
+    pub no_color: bool,
+    pub no_color_env: EnvChanges,
+    pub tool_colors: bool,
+    pub terminfo: bool,
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_color(spec: &str) -> Result<Rgb, String> {
+    Rgb::parse(spec.as_bytes()).ok_or_else(|| format!("invalid color: {spec}"))
+}
+
+/// Expands a leading `~/` in `path` to the home directory.
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+impl Options {
+    /// Sets the option with the long name `name`. Options that don't take a
+    /// value can be given one (e.g., `true` or `off`) to turn them on or off.
+    pub fn set(
+        &mut self,
+        name: &str,
+        value: Option<&str>,
+    ) -> Result<(), String> {
+        let takes_value = match OPTIONS.iter().find(|(_, n, _)| *n == name) {
+            Some(_) if COMMAND_LINE_ONLY.contains(&name) => None,
+            option => option.map(|o| o.2),
+        };
+        let value = match (takes_value, value) {
+            (None, _) => return Err(format!("unknown option: {name}")),
+            (Some(true), None) => {
+                return Err(format!("missing value for option: {name}"));
+            }
+            (Some(_), value) => value.unwrap_or("true"),
+        };
+        let flag = || {
+            parse_bool(value)
+                .ok_or_else(|| format!("invalid value for {name}: {value}"))
+        };
+        match name {
+            "bold" => self.bold = flag()?,
+            "c1-controls" => self.c1_controls = flag()?,
+            "grayscale" => {
+                self.mode = match (flag()?, self.mode) {
+                    (true, _) => Mode::Grayscale,
+                    (false, Mode::Grayscale) => Mode::Mono,
+                    (false, mode) => mode,
+                };
+            }
+            "mode" => {
+                self.mode = match value {
+                    "mono" => Mode::Mono,
+                    "grayscale" => Mode::Grayscale,
+                    "16" => Mode::Downsample(16),
+                    "256" => Mode::Downsample(256),
+                    _ => return Err(format!("invalid mode: {value}")),
+                };
+            }
+            "colors" => {
+                self.mode = match value {
+                    "16" => Mode::Downsample(16),
+                    "256" => Mode::Downsample(256),
+                    _ => {
+                        return Err(format!(
+                            "invalid number of colors: {value}"
+                        ));
+                    }
+                };
+            }
+            "truecolor" => self.truecolor = flag()?,
+            "gray-replies" => self.gray_replies = flag()?,
+            "levels" => {
+                self.mode = Mode::Grayscale;
+                self.levels = Some(Levels::parse(value)?);
+            }
+            "emphasis" => self.emphasis = flag()?,
+            "palette" => self.palette = Some(expand_home(value)),
+            "probe" => self.probe = flag()?,
+            "foreground" => self.foreground = Some(parse_color(value)?),
+            "background" => self.background = Some(parse_color(value)?),
+            "keep" => self.keep = ColorSet::parse(value)?,
+            "map" => self.color_map = ColorMap::parse(value)?,
+            "osc-colors" => self.osc_actions = OscActions::parse(value)?,
+            "no-color" => self.no_color = flag()?,
+            "no-color-env" => {
+                self.no_color = true;
+                self.no_color_env = EnvChanges::parse(value)?;
+            }
+            "tool-colors" => self.tool_colors = flag()?,
+            "terminfo" => self.terminfo = flag()?,
+            "toggle-key" => {
+                self.toggle_key = match value {
+                    "none" => None,
+                    _ => Some(Chord::parse(value)?),
+                };
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+/// Returns the environment variable that sets the option `name`, e.g.,
+/// `MONOTERM_OSC_COLORS` for `osc-colors`.
+fn env_var(name: &str) -> String {
+    format!("MONOTERM_{}", name.replace('-', "_").to_ascii_uppercase())
+}
+
+/// Returns the value of the environment variable `var`, if it's set and not
+/// empty.
+fn env_value(var: &str) -> Result<Option<String>, String> {
+    match env::var(var) {
+        Ok(value) => Ok(Some(value).filter(|v| !v.is_empty())),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(_)) => {
+            Err(format!("invalid value in {var}"))
+        }
+    }
+}
+
 struct ParsedArgs {
     pub command: Vec<OsString>,
-    pub bold: bool,
+    /// The configuration file given with `--config`.
+    pub config: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub control_socket: Option<PathBuf>,
+    /// The options given on the command line, in order.
+    pub options: Vec<(&'static str, Option<String>)>,
+}
+
+impl ParsedArgs {
+    /// Returns the path of the configuration file, and whether it was
+    /// specified explicitly.
+    pub fn config_path(&self) -> Option<(PathBuf, bool)> {
+        match &self.config {
+            Some(path) => Some((path.clone(), true)),
+            None => Config::default_path().map(|path| (path, false)),
+        }
+    }
+
+    /// Loads the configuration file, if it exists, and returns its path and
+    /// contents.
+    pub fn load_config(&self) -> Result<Option<(PathBuf, Config)>, String> {
+        let Some((path, explicit)) = self.config_path() else {
+            return Ok(None);
+        };
+        match Config::load(&path)? {
+            Some(config) => Ok(Some((path, config))),
+            None if explicit => Err(format!(
+                "could not read {}: file not found",
+                path.display(),
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Loads the options from the configuration file (its top-level settings
+    /// and then `profile`, if given), the environment, and the command line,
+    /// with later sources taking precedence.
+    pub fn load_options(
+        &self,
+        profile: Option<&str>,
+    ) -> Result<Options, String> {
+        let mut options = Options::default();
+        if let Some((path, config)) = &self.load_config()? {
+            let entries = match profile {
+                Some(name) => config
+                    .profile(name)
+                    .ok_or_else(|| format!("unknown profile: {name}"))?,
+                None => &[],
+            };
+            for entry in config.default.iter().chain(entries) {
+                options.set(&entry.key, Some(&entry.value)).map_err(|e| {
+                    format!("{}: line {}: {e}", path.display(), entry.line)
+                })?;
+            }
+        } else if let Some(name) = profile {
+            return Err(format!("unknown profile: {name}"));
+        }
+
+        for &(_, name, _) in OPTIONS {
+            if COMMAND_LINE_ONLY.contains(&name) {
+                continue;
+            }
+            let var = env_var(name);
+            if let Some(value) = env_value(&var)? {
+                options
+                    .set(name, Some(&value))
+                    .map_err(|e| format!("{var}: {e}"))?;
+            }
+        }
+
+        for (name, value) in &self.options {
+            options.set(name, value.as_deref())?;
+        }
+        Ok(options)
+    }
+}
+
+/// Returns the value of the option `name`, which is either `inline` (if
+/// present) or the next argument.
+fn option_value<Args>(
+    name: &str,
+    inline: Option<&[u8]>,
+    args: &mut Args,
+) -> String
+where
+    Args: Iterator<Item = OsString>,
+{
+    let value = match inline {
+        Some(value) => std::str::from_utf8(value).map(str::to_owned).ok(),
+        None => args
+            .next()
+            .unwrap_or_else(|| {
+                args_error!("missing value for option: --{name}")
+            })
+            .into_string()
+            .ok(),
+    };
+    value.unwrap_or_else(|| args_error!("invalid value for option: --{name}"))
 }
 
 fn parse_args<Args>(args: Args) -> ParsedArgs
 where
     Args: IntoIterator<Item = OsString>,
 {
-    let mut bold = false;
-    let mut options_done = false;
+    let mut args = args.into_iter();
+    let mut config = None;
+    let mut profile = None;
+    let mut control_socket = None;
+    let mut options = Vec::new();
+    // Validates the options as they're given, so that errors can be reported
+    // as usage errors.
+    let mut validator = Options::default();
 
-    // Returns whether `arg` should be part of the executed command.
-    let mut process_arg = |arg: &OsStr| {
+    let mut set_option = |name: &'static str, value: Option<String>| match name
+    {
+        "help" => show_usage(),
+        "version" => show_version(),
+        "config" => config = value.map(PathBuf::from),
+        "profile" => profile = value,
+        "control-socket" => control_socket = value.map(PathBuf::from),
+        _ => {
+            validator
+                .set(name, value.as_deref())
+                .unwrap_or_else(|e| args_error!("{e}"));
+            options.push((name, value));
+        }
+    };
+
+    let mut command = Vec::new();
+    while let Some(arg) = args.next() {
         let bytes = arg.as_encoded_bytes();
-        if options_done || arg == "-" {
+        if arg == "-" || !bytes.starts_with(b"-") {
+            command.push(arg);
+            break;
         } else if arg == "--" {
-            options_done = true;
-            return false;
-        } else if arg == "--help" {
-            show_usage();
-        } else if arg == "--version" {
-            show_version();
-        } else if arg == "--bold" {
-            bold = true;
-            return false;
-        } else if bytes.starts_with(b"--") {
-            args_error!("unrecognized option: {}", arg.to_string_lossy());
-        } else if let Some(opts) = bytes.strip_prefix(b"-") {
-            opts.iter().copied().for_each(|opt| match opt {
-                b'h' => show_usage(),
-                b'v' => show_version(),
-                b'b' => {
-                    bold = true;
-                }
-                _ if opt.is_ascii() => {
+            break;
+        } else if let Some(long) = bytes.strip_prefix(b"--") {
+            let (name, inline) = match long.iter().position(|b| *b == b'=') {
+                Some(i) => (&long[..i], Some(&long[i + 1..])),
+                None => (long, None),
+            };
+            let Some(&(_, name, takes_value)) =
+                OPTIONS.iter().find(|(_, n, _)| n.as_bytes() == name)
+            else {
+                args_error!("unrecognized option: {}", arg.to_string_lossy());
+            };
+            let value = if takes_value {
+                Some(option_value(name, inline, &mut args))
+            } else if inline.is_some() {
+                args_error!("option does not take a value: --{name}");
+            } else {
+                None
+            };
+            set_option(name, value);
+            continue;
+        }
+
+        let opts = &bytes[1..];
+        for (i, opt) in opts.iter().copied().enumerate() {
+            let Some(&(_, name, takes_value)) =
+                OPTIONS.iter().find(|(c, _, _)| *c == Some(opt))
+            else {
+                if opt.is_ascii() {
                     args_error!("unrecognized option: -{}", char::from(opt));
                 }
-                _ => {
-                    args_error!(
-                        "unrecognized option: {}",
-                        arg.to_string_lossy(),
-                    );
-                }
-            });
-            return false;
+                args_error!("unrecognized option: {}", arg.to_string_lossy());
+            };
+            if takes_value {
+                // The rest of the argument, if any, is the value.
+                let rest = Some(&opts[i + 1..]).filter(|r| !r.is_empty());
+                set_option(name, Some(option_value(name, rest, &mut args)));
+                break;
+            }
+            set_option(name, None);
         }
-        options_done = true;
-        true
-    };
+    }
 
-    let command: Vec<_> =
-        args.into_iter().filter(|a| process_arg(a)).collect();
+    command.extend(args);
     if command.is_empty() {
         eprint!("{USAGE}");
         exit(1);
     }
+    let from_env = |name| {
+        env_value(&env_var(name)).unwrap_or_else(|e| {
+            eprintln!("error: {e}");
+            exit(1);
+        })
+    };
     ParsedArgs {
         command,
-        bold,
+        config: config.or_else(|| from_env("config").map(PathBuf::from)),
+        profile: profile.or_else(|| from_env("profile")),
+        control_socket: control_socket
+            .or_else(|| from_env("control-socket").map(PathBuf::from)),
+        options,
+    }
+}
+
+/// Returns the filter settings for `options`, using `palette` as the
+/// terminal's palette.
+fn settings(
+    options: Options,
+    mut palette: Palette,
+) -> Result<Settings, String> {
+    if let Some(foreground) = options.foreground {
+        palette.foreground = foreground;
+    }
+    if let Some(background) = options.background {
+        palette.background = background;
+    }
+    let levels = match options.levels {
+        // Levels are kept apart by the palette grays that represent them.
+        Some(levels)
+            if options.mode == Mode::Grayscale && !options.truecolor =>
+        {
+            let text = levels.to_string();
+            let levels = levels.dedup_by_key(|l| palette.nearest_gray(l));
+            Some(levels.ok_or_else(|| {
+                format!(
+                    "gray levels {text} are all shown as the same palette \
+                     gray (use --truecolor)"
+                )
+            })?)
+        }
+        levels => levels,
+    };
+    Ok(Settings {
+        sgr: SgrSettings {
+            mode: options.mode,
+            bold_colors: options.bold,
+            emphasis: options.emphasis,
+            color_map: options.color_map,
+            keep: options.keep,
+            truecolor_grays: options.truecolor,
+            levels,
+            palette,
+        },
+        c1_mode: if options.c1_controls {
+            C1Mode::from_locale()
+        } else {
+            C1Mode::Disabled
+        },
+        // Downsampling is meant to preserve colors, so color changes are kept
+        // unless specified otherwise.
+        osc_actions: options.osc_actions.or(match options.mode {
+            Mode::Downsample(_) => OscAction::Keep,
+            _ => OscAction::Gray,
+        }),
+        gray_replies: options.gray_replies,
+        toggle_key: options.toggle_key,
+    })
+}
+
+/// Reloads the options of a running session.
+struct Reloader {
+    args: ParsedArgs,
+    /// The profile in use.
+    profile: Mutex<Option<String>>,
+    /// Options set while the child is running, which take precedence over
+    /// all other sources.
+    overrides: Mutex<Vec<(String, String)>>,
+    /// The palette reported by the terminal at startup, if it was probed.
+    /// (The terminal can't be probed again once the child is running.)
+    probed: Option<Palette>,
+    pub control: Control,
+}
+
+impl Reloader {
+    fn load(&self, profile: Option<&str>) -> Result<(), String> {
+        let mut options = self.args.load_options(profile)?;
+        for (name, value) in self.overrides.lock().unwrap().iter() {
+            options.set(name, Some(value))?;
+        }
+        let palette = match (&self.probed, &options.palette) {
+            (Some(probed), _) if options.probe => probed.clone(),
+            (_, Some(path)) => Palette::load(path)?,
+            (_, None) => Palette::default(),
+        };
+        self.control.send(settings(options, palette)?);
+        Ok(())
+    }
+
+    pub fn reload(&self) -> Result<(), String> {
+        self.load(self.profile.lock().unwrap().as_deref())
+    }
+
+    pub fn profile(&self) -> Option<String> {
+        self.profile.lock().unwrap().clone()
+    }
+
+    /// Sets the option with the long name `name` to `value`, as if it were
+    /// given on the command line, and reloads the options.
+    pub fn set(&self, name: &str, value: &str) -> Result<(), String> {
+        Options::default().set(name, Some(value))?;
+        if STARTUP_ONLY.contains(&name) {
+            return Err(format!("{name} can be set only at startup"));
+        }
+        let profile = self.profile.lock().unwrap();
+        let saved = {
+            let mut overrides = self.overrides.lock().unwrap();
+            let saved = overrides.clone();
+            // The new value is applied last, after other options that affect
+            // the same setting (e.g., `mode` and `levels`).
+            overrides.retain(|(n, _)| n != name);
+            overrides.push((name.to_owned(), value.to_owned()));
+            saved
+        };
+        let result = self.load(profile.as_deref());
+        if result.is_err() {
+            *self.overrides.lock().unwrap() = saved;
+        }
+        result
+    }
+
+    /// Switches to the next profile in the configuration file. After the
+    /// last profile, no profile is used (only the top-level settings).
+    pub fn next_profile(&self) -> Result<(), String> {
+        let names: Vec<_> = match self.args.load_config()? {
+            Some((_, config)) => {
+                config.profiles.into_iter().map(|(name, _)| name).collect()
+            }
+            None => Vec::new(),
+        };
+        let mut profile = self.profile.lock().unwrap();
+        let next = match profile.as_ref() {
+            Some(current) => names
+                .iter()
+                .position(|name| name == current)
+                .map_or(names.first(), |i| names.get(i + 1)),
+            None => names.first(),
+        };
+        self.load(next.map(String::as_str))?;
+        *profile = next.cloned();
+        Ok(())
     }
 }
 
 fn main() {
-    let args = parse_args(env::args_os().skip(1));
-    let mut filter = Filter::new(args.bold);
-    if let Err(e) = filterm::run(args.command, &mut filter) {
+    let mut args = parse_args(env::args_os().skip(1));
+    let options =
+        args.load_options(args.profile.as_deref()).unwrap_or_else(|e| {
+            eprintln!("error: {e}");
+            exit(1);
+        });
+    let mut palette = match &options.palette {
+        Some(path) => Palette::load(path).unwrap_or_else(|e| {
+            eprintln!("error: {e}");
+            exit(1);
+        }),
+        None => Palette::default(),
+    };
+    if options.probe {
+        probe::probe(&mut palette);
+    }
+    let probed = options.probe.then(|| palette.clone());
+    let settings = settings(options.clone(), palette).unwrap_or_else(|e| {
+        eprintln!("error: {e}");
+        exit(1);
+    });
+    if options.terminfo {
+        let keep_colors = !options.keep.is_empty();
+        match terminfo::install(options.mode, keep_colors) {
+            Ok(name) => env::set_var("TERM", name),
+            Err(e) => eprintln!("warning: {e}"),
+        }
+    }
+    if options.no_color {
+        options.no_color_env.apply(settings.sgr.palette.background);
+    }
+    if options.tool_colors {
+        tool_colors::apply(&settings.sgr);
+    }
+    let mut filter = Filter::new(settings);
+
+    let command = mem::take(&mut args.command);
+    let config_path = args.config_path();
+    let control_socket = args.control_socket.clone();
+    let reloader = Arc::new(Reloader {
+        profile: Mutex::new(args.profile.clone()),
+        args,
+        probed,
+        overrides: Mutex::default(),
+        control: filter.control(),
+    });
+    // Invalid settings leave the current settings in place.
+    if let Some((path, _)) = config_path {
+        let reloader = reloader.clone();
+        let result = watch::watch(&path, move || {
+            let _ = reloader.reload();
+        });
+        if let Err(e) = result {
+            eprintln!("warning: {e}");
+        }
+    }
+    let signal_reloader = reloader.clone();
+    let result = signals::handle(move |signal| match signal {
+        Signal::SIGUSR1 => signal_reloader.control.toggle(),
+        Signal::SIGUSR2 => {
+            let _ = signal_reloader.next_profile();
+        }
+        _ => {}
+    });
+    if let Err(e) = result {
+        eprintln!("warning: {e}");
+    }
+    if let Some(path) = &control_socket {
+        control::listen(path, reloader).unwrap_or_else(|e| {
+            eprintln!("error: {e}");
+            exit(1);
+        });
+    }
+    let result = filterm::run(command, &mut filter);
+    if let Some(path) = &control_socket {
+        let _ = fs::remove_file(path);
+    }
+    if let Err(e) = result {
         eprintln!("error: {e}");
         exit(1);
     }
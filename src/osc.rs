@@ -0,0 +1,373 @@
+/*
+ * Copyright (C) 2026 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of Monoterm.
+ *
+ * Monoterm is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Monoterm is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Monoterm. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Handling of operating system commands (OSCs) that change the terminal's
+//! colors.
+
+use crate::color::Rgb;
+
+/// Maximum total size of the data written in place of a single intercepted
+/// OSC. Color changes beyond this limit are dropped.
+const OUTPUT_MAX_LEN: usize = 16384;
+
+/// What to do with a color-changing OSC.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum OscAction {
+    /// Forward the OSC unchanged.
+    Keep,
+    /// Drop the OSC.
+    Drop,
+    /// Replace each color with the gray of the same luminance. OSCs that
+    /// reset colors are forwarded unchanged.
+    Gray,
+}
+
+impl OscAction {
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "keep" => Self::Keep,
+            "drop" => Self::Drop,
+            "gray" | "grey" => Self::Gray,
+            _ => return None,
+        })
+    }
+}
+
+/// Returns whether `command` is one of the OSCs handled by this module:
+///
+/// * OSC 4, which changes palette colors;
+/// * OSC 10 to 19, which change the dynamic colors (e.g., the default
+///   foreground and background);
+/// * OSC 21, kitty's color control protocol;
+/// * OSC 104 and 110 to 119, which reset the colors set by the above.
+fn is_color_osc(command: u16) -> bool {
+    matches!(command, 4 | 10..=19 | 21 | 104 | 110..=119)
+}
+
+/// The [`OscAction`] to take for each color-changing OSC. OSCs without an
+/// action are kept.
+#[derive(Clone)]
+pub struct OscActions([Option<OscAction>; 120]);
+
+impl OscActions {
+    pub fn new(action: OscAction) -> Self {
+        Self([Some(action); 120])
+    }
+
+    /// Sets the action of the OSCs that don't have one to `action`.
+    pub fn or(mut self, action: OscAction) -> Self {
+        self.0.iter_mut().for_each(|a| {
+            a.get_or_insert(action);
+        });
+        self
+    }
+
+    fn get(&self, command: u16) -> OscAction {
+        let action = self.0.get(usize::from(command)).copied().flatten();
+        match action.unwrap_or(OscAction::Keep) {
+            OscAction::Gray if command >= 100 => OscAction::Keep,
+            action => action,
+        }
+    }
+
+    /// Parses a comma-separated list of actions. Each item is either an
+    /// action, which applies to all OSCs, or `<number>=<action>`, which
+    /// applies to a single OSC. OSCs not covered by any item have no action.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut actions = Self::default();
+        for item in spec.split(',') {
+            let (command, action) = match item.split_once('=') {
+                Some((command, action)) => (Some(command), action),
+                None => (None, item),
+            };
+            let action = OscAction::parse(action)
+                .ok_or_else(|| format!("invalid OSC action: {action}"))?;
+            let Some(command) = command else {
+                actions = Self::new(action);
+                continue;
+            };
+            let n = command
+                .parse()
+                .ok()
+                .filter(|n| is_color_osc(*n))
+                .ok_or_else(|| format!("unsupported OSC: {command}"))?;
+            actions.0[usize::from(n)] = Some(action);
+        }
+        Ok(actions)
+    }
+}
+
+impl Default for OscActions {
+    /// Returns actions in which no OSC has an action.
+    fn default() -> Self {
+        Self([None; 120])
+    }
+}
+
+/// Rewrites or drops color-changing OSCs according to an [`OscActions`].
+pub struct OscFilter {
+    actions: OscActions,
+    /// The command number of the intercepted OSC.
+    command: u16,
+    /// The number of fields of the intercepted OSC received so far.
+    fields: usize,
+    /// In OSC 4, the color index from the previous field.
+    index: Vec<u8>,
+    /// The OSCs to write in place of the intercepted one, as command numbers
+    /// and data.
+    output: Vec<(u16, Vec<u8>)>,
+    output_len: usize,
+}
+
+impl OscFilter {
+    pub fn new(actions: OscActions) -> Self {
+        Self {
+            actions,
+            command: 0,
+            fields: 0,
+            index: Vec::new(),
+            output: Vec::new(),
+            output_len: 0,
+        }
+    }
+
+    /// Changes the actions taken for OSCs that start after this call.
+    pub fn set_actions(&mut self, actions: OscActions) {
+        self.actions = actions;
+    }
+
+    /// Returns whether the OSC with the given command number should be
+    /// intercepted.
+    pub fn start(&mut self, command: &[u8]) -> bool {
+        let Some(n) = (|| std::str::from_utf8(command).ok()?.parse().ok())()
+        else {
+            return false;
+        };
+        let action = |n| self.actions.get(n);
+        let intercept = match n {
+            4 | 21 => action(n) != OscAction::Keep,
+            10..=19 => (n..=19).any(|n| action(n) != OscAction::Keep),
+            104 | 110..=119 => action(n) == OscAction::Drop,
+            _ => false,
+        };
+        if intercept {
+            self.command = n;
+            self.fields = 0;
+            self.cancel();
+        }
+        intercept
+    }
+
+    /// Returns the replacement for the color specification `spec` in the OSC
+    /// with the given command number, or [`None`] if it should be dropped.
+    fn convert(&self, command: u16, spec: &[u8]) -> Option<Vec<u8>> {
+        // Queries and resets are forwarded.
+        if let b"?" | b"" = spec {
+            return Some(spec.to_vec());
+        }
+        match self.actions.get(command) {
+            OscAction::Keep => Some(spec.to_vec()),
+            OscAction::Drop => None,
+            OscAction::Gray => {
+                // Keep the precision of `rgb:` specifications, which are
+                // also used in the terminal's replies to queries.
+                let digits = spec
+                    .strip_prefix(b"rgb:")
+                    .and_then(|s| s.split(|b| *b == b'/').next())
+                    .map_or(2, |c| c.len());
+                let gray = Rgb::parse(spec)?.to_gray();
+                Some(gray.to_x11(digits).into_bytes())
+            }
+        }
+    }
+
+    fn push(&mut self, command: u16, data: &[u8]) {
+        if self.output_len + data.len() > OUTPUT_MAX_LEN {
+            return;
+        }
+        self.output_len += data.len();
+        match self.output.last_mut() {
+            // OSC 4 and 21 can hold multiple colors.
+            Some((n, output))
+                if *n == command && matches!(command, 4 | 21) =>
+            {
+                output.push(b';');
+                output.extend_from_slice(data);
+            }
+            _ => self.output.push((command, data.to_vec())),
+        }
+    }
+
+    pub fn field(&mut self, field: &[u8]) {
+        let i = self.fields;
+        self.fields += 1;
+        match self.command {
+            // OSC 4 alternates between color indices and specifications.
+            4 if i % 2 == 0 => {
+                self.index.clear();
+                self.index.extend_from_slice(field);
+            }
+            4 => {
+                if let Some(spec) = self.convert(4, field) {
+                    let item = [&self.index, &b";"[..], &spec].concat();
+                    self.push(4, &item);
+                }
+            }
+            // OSC 10 to 19 apply each field to the next dynamic color.
+            n @ 10..=19 => {
+                let n = n + i.min(10) as u16;
+                if n > 19 {
+                    return;
+                }
+                if let Some(spec) = self.convert(n, field) {
+                    self.push(n, &spec);
+                }
+            }
+            // OSC 21 consists of `key=value` pairs.
+            21 => {
+                let (key, value) = match field.iter().position(|b| *b == b'=')
+                {
+                    Some(i) => (&field[..=i], &field[i + 1..]),
+                    None => (field, &b""[..]),
+                };
+                if let Some(spec) = self.convert(21, value) {
+                    self.push(21, &[key, &spec].concat());
+                }
+            }
+            // The OSC resets colors and is being dropped.
+            _ => {}
+        }
+    }
+
+    pub fn end(&mut self, terminator: &[u8], write: &mut dyn FnMut(&[u8])) {
+        for (n, data) in &self.output {
+            write(b"\x1b]");
+            write(n.to_string().as_bytes());
+            write(b";");
+            write(data);
+            write(terminator);
+        }
+        self.cancel();
+    }
+
+    pub fn cancel(&mut self) {
+        self.output.clear();
+        self.output_len = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Filters the OSC whose command and fields are `osc`, separated by
+    /// `;`, with the actions in `spec`. Returns the OSCs written in its
+    /// place, or `None` if it isn't intercepted.
+    fn filter(spec: &str, osc: &str) -> Option<String> {
+        let mut filter = OscFilter::new(OscActions::parse(spec).unwrap());
+        let mut fields = osc.split(';');
+        if !filter.start(fields.next().unwrap().as_bytes()) {
+            return None;
+        }
+        fields.for_each(|field| filter.field(field.as_bytes()));
+        let mut output = Vec::new();
+        filter.end(b"\x07", &mut |b| output.extend_from_slice(b));
+        Some(String::from_utf8(output).unwrap().replace("\x1b]", "]"))
+    }
+
+    #[test]
+    fn palette() {
+        assert_eq!(
+            filter("gray", "4;1;#ff0000;2;rgb:0000/ffff/0000;3;?"),
+            Some("]4;1;rgb:7f/7f/7f;2;rgb:dcdc/dcdc/dcdc;3;?\x07".to_owned()),
+        );
+        assert_eq!(
+            filter("gray", "4;1;invalid;2;#000"),
+            Some("]4;2;rgb:00/00/00\x07".to_owned()),
+        );
+        assert_eq!(filter("drop", "4;1;#ff0000"), Some(String::new()));
+        assert_eq!(filter("4=drop", "4;1;?"), Some("]4;1;?\x07".to_owned()));
+        assert_eq!(filter("keep", "4;1;#ff0000"), None);
+        assert_eq!(filter("10=drop", "4;1;#ff0000"), None);
+    }
+
+    #[test]
+    fn dynamic_colors() {
+        // Each field applies to the next dynamic color.
+        assert_eq!(
+            filter("11=drop,12=gray", "10;#ff0000;#00ff00;#0000ff"),
+            Some("]10;#ff0000\x07]12;rgb:4c/4c/4c\x07".to_owned()),
+        );
+        assert_eq!(
+            filter("gray", "19;#ffffff;#000000"),
+            Some("]19;rgb:ff/ff/ff\x07".to_owned()),
+        );
+        // OSC 10 is intercepted if a later dynamic color has an action.
+        assert_eq!(
+            filter("11=drop", "10;#ff0000;#00ff00"),
+            Some("]10;#ff0000\x07".to_owned()),
+        );
+        // Queries are forwarded even if colors are dropped.
+        assert_eq!(
+            filter("drop", "10;?;?"),
+            Some("]10;?\x07]11;?\x07".to_owned()),
+        );
+        assert_eq!(filter("10=drop", "11;#ff0000"), None);
+    }
+
+    #[test]
+    fn kitty_colors() {
+        assert_eq!(
+            filter("gray", "21;foreground=#ff0000;cursor;1=?;background="),
+            Some(
+                "]21;foreground=rgb:7f/7f/7f;cursor;1=?;background=\x07"
+                    .to_owned()
+            ),
+        );
+        assert_eq!(
+            filter("drop", "21;foreground=#ff0000"),
+            Some(String::new())
+        );
+    }
+
+    #[test]
+    fn resets() {
+        assert_eq!(filter("drop", "104;1"), Some(String::new()));
+        assert_eq!(filter("drop", "110"), Some(String::new()));
+        assert_eq!(filter("drop", "119"), Some(String::new()));
+        // Resets aren't grayed.
+        assert_eq!(filter("gray", "104;1"), None);
+        assert_eq!(filter("gray", "111"), None);
+        assert_eq!(filter("drop", "120"), None);
+    }
+
+    #[test]
+    fn parse() {
+        assert!(OscActions::parse("gray,10=keep,104=drop").is_ok());
+        assert!(OscActions::parse("3=drop").is_err());
+        assert!(OscActions::parse("4=red").is_err());
+        let actions = OscActions::parse("10=drop").unwrap();
+        assert!(actions.get(10) == OscAction::Drop);
+        assert!(actions.get(4) == OscAction::Keep);
+        let actions = actions.or(OscAction::Gray);
+        assert!(actions.get(10) == OscAction::Drop);
+        assert!(actions.get(4) == OscAction::Gray);
+        assert!(actions.get(110) == OscAction::Keep);
+    }
+}
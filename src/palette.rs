@@ -0,0 +1,359 @@
+/*
+ * Copyright (C) 2026 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of Monoterm.
+ *
+ * Monoterm is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Monoterm is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Monoterm. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The terminal's color palette, and loading it from theme files.
+
+use crate::color::{Color, Rgb};
+use crate::style::COLOR_NAMES;
+use std::collections::HashMap;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// Returns the squared perceptual distance (CIE 1976) between two colors in
+/// CIELAB.
+fn lab_distance(a: [f64; 3], b: [f64; 3]) -> f64 {
+    (0..3).map(|i| (a[i] - b[i]).powi(2)).sum()
+}
+
+/// The colors of the 256-color palette, and the default foreground and
+/// background colors.
+#[derive(Clone)]
+pub struct Palette {
+    pub foreground: Rgb,
+    pub background: Rgb,
+    colors: [Rgb; 256],
+    /// The colors in CIELAB, for finding the nearest color.
+    labs: [[f64; 3]; 256],
+}
+
+/// The first 16 colors of xterm's default palette.
+const XTERM_BASIC: [Rgb; 16] = [
+    Rgb::new(0x00, 0x00, 0x00),
+    Rgb::new(0xcd, 0x00, 0x00),
+    Rgb::new(0x00, 0xcd, 0x00),
+    Rgb::new(0xcd, 0xcd, 0x00),
+    Rgb::new(0x00, 0x00, 0xee),
+    Rgb::new(0xcd, 0x00, 0xcd),
+    Rgb::new(0x00, 0xcd, 0xcd),
+    Rgb::new(0xe5, 0xe5, 0xe5),
+    Rgb::new(0x7f, 0x7f, 0x7f),
+    Rgb::new(0xff, 0x00, 0x00),
+    Rgb::new(0x00, 0xff, 0x00),
+    Rgb::new(0xff, 0xff, 0x00),
+    Rgb::new(0x5c, 0x5c, 0xff),
+    Rgb::new(0xff, 0x00, 0xff),
+    Rgb::new(0x00, 0xff, 0xff),
+    Rgb::new(0xff, 0xff, 0xff),
+];
+
+impl Palette {
+    /// Returns xterm's default palette: 16 basic colors, a 6x6x6 color cube
+    /// (16-231), and a 24-step gray ramp (232-255). The default colors are
+    /// white on black, as most terminals are configured.
+    pub fn xterm() -> Self {
+        let mut colors = [Rgb::gray(0); 256];
+        colors[..16].copy_from_slice(&XTERM_BASIC);
+        let level = |i: usize| {
+            if i == 0 {
+                0
+            } else {
+                55 + 40 * i as u8
+            }
+        };
+        for i in 0..216 {
+            colors[16 + i] =
+                Rgb::new(level(i / 36), level(i / 6 % 6), level(i % 6));
+        }
+        for i in 0..24 {
+            colors[232 + i] = Rgb::gray(8 + 10 * i as u8);
+        }
+        Self {
+            foreground: Rgb::gray(0xff),
+            background: Rgb::gray(0x00),
+            colors,
+            labs: colors.map(Rgb::to_lab),
+        }
+    }
+
+    pub fn get(&self, index: u8) -> Rgb {
+        self.colors[usize::from(index)]
+    }
+
+    /// Returns the perceptual distance (CIE 1976 delta E) between `rgb` and
+    /// the color at `index`.
+    pub fn distance(&self, rgb: Rgb, index: u8) -> f64 {
+        lab_distance(rgb.to_lab(), self.labs[usize::from(index)]).sqrt()
+    }
+
+    /// Returns the index of the color in `range` perceptually closest to
+    /// `rgb`.
+    pub fn nearest(&self, rgb: Rgb, range: RangeInclusive<u8>) -> u8 {
+        let lab = rgb.to_lab();
+        let start = *range.start();
+        range
+            .map(|i| (i, lab_distance(lab, self.labs[usize::from(i)])))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(start, |(i, _)| i)
+    }
+
+    /// Returns the RGB value of `color`.
+    pub fn rgb(&self, color: Color) -> Rgb {
+        match color {
+            Color::Indexed(i) => self.get(i),
+            Color::Rgb(rgb) => rgb,
+        }
+    }
+
+    /// Returns the index of the gray in the palette closest to `level`,
+    /// choosing from the gray ramp and the black and white corners of the
+    /// color cube.
+    pub fn nearest_gray(&self, level: u8) -> u8 {
+        [16, 231]
+            .into_iter()
+            .chain(232..=255)
+            .min_by_key(|i| {
+                let c = self.get(*i);
+                let diff = |v: u8| u32::from(v.abs_diff(level));
+                diff(c.r) + diff(c.g) + diff(c.b)
+            })
+            .unwrap_or(16)
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::xterm()
+    }
+}
+
+/// An entry in a palette file.
+enum Entry {
+    Index(u8),
+    Foreground,
+    Background,
+}
+
+/// Returns the entry that `key` sets in `section` (the most recent
+/// `[section]` header, or an empty string if none).
+fn entry(section: &str, key: &str) -> Option<Entry> {
+    let basic = |key: &str| COLOR_NAMES[..8].iter().position(|n| *n == key);
+    let number = |key: &str, max: u8| {
+        key.parse().ok().filter(|n| *n <= max).filter(|_| {
+            // Reject forms like `+1` and `01`.
+            !key.starts_with(['+', '0']) || key == "0"
+        })
+    };
+    let index = match section {
+        // Alacritty.
+        "colors.normal" => basic(key)? as u8,
+        "colors.bright" => basic(key)? as u8 + 8,
+        "" | "colors" | "colors-dark" | "colors.primary" => match key {
+            "foreground" => return Some(Entry::Foreground),
+            "background" => return Some(Entry::Background),
+            // Foot.
+            _ if key.starts_with("regular") => number(&key[7..], 7)?,
+            _ if key.starts_with("bright") => number(&key[6..], 7)? + 8,
+            // Xresources and kitty.
+            _ if key.starts_with("color") => number(&key[5..], 255)?,
+            // Foot (for 16-255) and the simple format.
+            _ => number(key, 255)?,
+        },
+        _ => return None,
+    };
+    Some(Entry::Index(index))
+}
+
+/// Parses a color in a palette file: an X11 color specification (e.g.,
+/// `#rrggbb`), `0xrrggbb`, or bare `rrggbb`.
+fn parse_color(value: &str) -> Option<Rgb> {
+    let hex = value.strip_prefix("0x").unwrap_or(value);
+    if hex.len() == 6 && hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Rgb::parse(format!("#{hex}").as_bytes());
+    }
+    Rgb::parse(value.as_bytes())
+}
+
+impl Palette {
+    /// Sets the color at `index`.
+    pub fn set(&mut self, index: u8, rgb: Rgb) {
+        self.colors[usize::from(index)] = rgb;
+        self.labs[usize::from(index)] = rgb.to_lab();
+    }
+
+    /// Parses a palette definition in one of several common formats:
+    ///
+    /// * Xresources: `*color3: #rrggbb`, `*foreground: #rrggbb`
+    /// * Alacritty (TOML): `[colors.normal]`, `yellow = "#rrggbb"`
+    /// * kitty: `color3 #rrggbb`
+    /// * foot (INI): `[colors]`, `regular3=rrggbb`
+    /// * A simple list: `3=#rrggbb`
+    ///
+    /// Colors not defined by the file keep their default values, and unknown
+    /// settings are ignored.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut palette = Self::xterm();
+        let mut section = String::new();
+        // Xresources macros.
+        let mut defines = HashMap::new();
+        // The index from the previous line, in Alacritty's indexed colors.
+        let mut index = None;
+        let mut found = false;
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if let Some(define) = line.strip_prefix("#define") {
+                let mut parts = define.split_whitespace();
+                if let (Some(name), Some(value)) = (parts.next(), parts.next())
+                {
+                    defines.insert(name, value);
+                }
+                continue;
+            }
+            if line.is_empty() || line.starts_with(['#', '!', ';']) {
+                continue;
+            }
+            if line.starts_with('[') {
+                section = line.trim_matches(['[', ']']).trim().to_owned();
+                index = None;
+                continue;
+            }
+
+            let (key, value) =
+                line.split_once([':', '=', ' ', '\t']).unwrap_or((line, ""));
+            // Remove Xresources prefixes, e.g., `URxvt*`.
+            let key = key.trim().rsplit(['*', '.']).next().unwrap_or(key);
+            let value = value.trim().trim_start_matches([':', '=']).trim();
+            let value = match value.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    value[1..].split(quote).next().unwrap_or(value)
+                }
+                _ => value.split_whitespace().next().unwrap_or(value),
+            };
+            let value = defines.get(value).copied().unwrap_or(value);
+
+            let entry = if section == "colors.indexed_colors" {
+                match key {
+                    "index" => {
+                        index = value.parse().ok();
+                        continue;
+                    }
+                    "color" => index.take().map(Entry::Index),
+                    _ => None,
+                }
+            } else {
+                entry(&section, key)
+            };
+            let Some(entry) = entry else {
+                continue;
+            };
+            let rgb = parse_color(value).ok_or_else(|| {
+                format!("line {}: invalid color: {value}", i + 1)
+            })?;
+            match entry {
+                Entry::Index(i) => palette.set(i, rgb),
+                Entry::Foreground => palette.foreground = rgb,
+                Entry::Background => palette.background = rgb,
+            }
+            found = true;
+        }
+
+        if !found {
+            return Err("no colors found".to_owned());
+        }
+        Ok(palette)
+    }
+
+    /// Loads a palette definition from a file (see [`Self::parse`]).
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let display = path.display();
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("could not read {display}: {e}"))?;
+        Self::parse(&text).map_err(|e| format!("{display}: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses `text` and checks that it sets color 3 (yellow) and the
+    /// default foreground, leaving the other colors unchanged.
+    fn check(text: &str) {
+        let palette = Palette::parse(text).unwrap();
+        let xterm = Palette::xterm();
+        assert_eq!(palette.get(3), Rgb::new(0x12, 0x34, 0x56), "{text}");
+        assert_eq!(palette.foreground, Rgb::new(0xab, 0xcd, 0xef), "{text}");
+        assert_eq!(palette.background, xterm.background, "{text}");
+        for i in (0..=255).filter(|i| *i != 3) {
+            assert_eq!(palette.get(i), xterm.get(i), "{text}: {i}");
+        }
+    }
+
+    #[test]
+    fn xresources() {
+        check("*color3: #123456\n*foreground: #abcdef\n");
+        check("URxvt.color3:\t#123456\nURxvt*foreground: rgb:ab/cd/ef\n");
+        check(
+            "! comment\n#define yellow #123456\n*.color3: yellow\n\
+             *foreground: #abcdef\n",
+        );
+    }
+
+    #[test]
+    fn alacritty() {
+        check(
+            "[colors.primary]\nforeground = \"#abcdef\"\n\
+             [colors.normal]\nyellow = '0x123456'\n",
+        );
+        check(
+            "[colors]\nforeground = \"#abcdef\"\n\
+             [[colors.indexed_colors]]\nindex = 3\ncolor = \"#123456\"\n",
+        );
+    }
+
+    #[test]
+    fn kitty() {
+        check("# comment\ncolor3 #123456\nforeground   #abcdef\n");
+    }
+
+    #[test]
+    fn foot() {
+        check("[colors]\nregular3=123456\nforeground=abcdef\n");
+        let palette = Palette::parse("[colors]\nbright1=123456\n").unwrap();
+        assert_eq!(palette.get(9), Rgb::new(0x12, 0x34, 0x56));
+    }
+
+    #[test]
+    fn simple() {
+        check("3=#123456\nforeground=#abcdef\n");
+        let palette = Palette::parse("200 = #123456").unwrap();
+        assert_eq!(palette.get(200), Rgb::new(0x12, 0x34, 0x56));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(Palette::parse("").is_err());
+        assert!(Palette::parse("[other]\ncolor3 #123456\n").is_err());
+        assert!(Palette::parse("color03 #123456\n").is_err());
+        assert!(Palette::parse("color256 #123456\n").is_err());
+        let error = Palette::parse("\ncolor3 #12345z\n").err().unwrap();
+        assert_eq!(error, "line 2: invalid color: #12345z");
+    }
+}
@@ -0,0 +1,1098 @@
+/*
+ * Copyright (C) 2026 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of Monoterm.
+ *
+ * Monoterm is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Monoterm is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Monoterm. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! An ECMA-48 parser modeled on the DEC VT500 state machine.
+//!
+//! The parser identifies the boundaries of escape sequences, control
+//! sequences, and control strings, and forwards everything except complete
+//! control sequences unchanged. Control sequences are passed to a [`Perform`]
+//! implementation, which decides what to write in their place. (Escape and
+//! control sequences interrupted by another sequence are dropped, as the
+//! terminal discards them.)
+
+use std::env;
+use std::mem;
+
+/// Maximum number of bytes buffered for a single control sequence or control
+/// string header. Control sequences without a private marker that exceed
+/// this length have their parameters streamed to [`Perform::csi_stream`];
+/// other sequences that exceed it are forwarded to the parent terminal
+/// unmodified.
+const SEQUENCE_MAX_LEN: usize = 128;
+
+/// Maximum number of significant digits stored for a single parameter or
+/// subparameter. Further digits are dropped; values this large aren't
+/// meaningful in any sequence this parser's users interpret.
+const PARAM_MAX_DIGITS: usize = 5;
+
+/// Maximum number of subparameters stored for a single parameter. Further
+/// subparameters are dropped.
+const PARAM_MAX_SUBPARAMS: usize = 8;
+
+const ESC: u8 = 0x1b;
+
+/// The string terminator (ST).
+const ST: &[u8] = b"\x1b\\";
+
+/// How 8-bit (C1) controls are recognized.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum C1Mode {
+    /// Only the 7-bit forms (e.g., `ESC [`) are recognized.
+    Disabled,
+    /// Single bytes in the range 0x80 to 0x9f are C1 controls.
+    EightBit,
+    /// The UTF-8 encodings of U+0080 to U+009F (0xc2 0x80 to 0xc2 0x9f) are
+    /// C1 controls. (A lone byte in that range is a UTF-8 continuation byte.)
+    Utf8,
+}
+
+impl C1Mode {
+    /// Returns [`Self::Utf8`] if the current locale uses UTF-8, or
+    /// [`Self::EightBit`] otherwise.
+    pub fn from_locale() -> Self {
+        let codeset = ["LC_ALL", "LC_CTYPE", "LANG"]
+            .into_iter()
+            .filter_map(env::var_os)
+            .find(|v| !v.is_empty())
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_ascii_lowercase();
+        if codeset.contains("utf-8") || codeset.contains("utf8") {
+            Self::Utf8
+        } else {
+            Self::EightBit
+        }
+    }
+}
+
+/// A complete control sequence.
+pub struct Csi<'a> {
+    /// The private parameter marker (one of `<=>?`), if present.
+    pub private: Option<u8>,
+    /// The parameter bytes (digits, `;`, and `:`). Leading zeros are removed
+    /// and overly long parameters are truncated (see [`PARAM_MAX_DIGITS`] and
+    /// [`PARAM_MAX_SUBPARAMS`]). If the start of the sequence was passed to
+    /// [`Perform::csi_stream`], this contains only the parameters received
+    /// after the last call.
+    pub params: &'a [u8],
+    pub intermediates: &'a [u8],
+    pub final_byte: u8,
+    /// C0 controls that appeared within the sequence. The terminal executes
+    /// these as they arrive, without interrupting the sequence.
+    pub controls: &'a [u8],
+    /// The entire sequence exactly as received, or an empty slice if the
+    /// start of the sequence was passed to [`Perform::csi_stream`].
+    pub raw: &'a [u8],
+}
+
+impl Csi<'_> {
+    /// Whether this is a Select Graphic Rendition sequence.
+    pub fn is_sgr(&self) -> bool {
+        self.final_byte == b'm'
+            && self.private.is_none()
+            && self.intermediates.is_empty()
+    }
+}
+
+pub trait Perform {
+    /// Called for each complete control sequence. To forward the sequence
+    /// unchanged, write [`csi.raw`](Csi::raw).
+    fn csi_dispatch(&mut self, csi: &Csi<'_>, write: &mut dyn FnMut(&[u8]));
+
+    /// Called when a control sequence without a private marker becomes too
+    /// long to buffer. `params` contains the complete parameters received
+    /// since the last call, each followed by `;`, and `controls` contains any
+    /// C0 controls received since the start of the sequence. (Later controls
+    /// are written directly.)
+    ///
+    /// Once this method has been called, the sequence can no longer be
+    /// forwarded unchanged. It will end with a call to either
+    /// [`Self::csi_dispatch`] or [`Self::csi_cancel`].
+    fn csi_stream(
+        &mut self,
+        params: &[u8],
+        controls: &[u8],
+        write: &mut dyn FnMut(&[u8]),
+    );
+
+    /// Called when a sequence passed to [`Self::csi_stream`] is canceled or
+    /// becomes invalid. Anything written for the sequence so far should be
+    /// canceled.
+    fn csi_cancel(&mut self, write: &mut dyn FnMut(&[u8]));
+
+    /// Called when the command number of an operating system command (OSC)
+    /// has been received. If this method returns true, the rest of the OSC is
+    /// passed to [`Self::osc_field`] instead of being forwarded.
+    fn osc_start(&mut self, command: &[u8]) -> bool;
+
+    /// Called for each `;`-separated field of an intercepted OSC, after the
+    /// command number. Fields longer than [`SEQUENCE_MAX_LEN`] are truncated.
+    fn osc_field(&mut self, field: &[u8]);
+
+    /// Called when an intercepted OSC ends with the string terminator
+    /// `terminator` (BEL or ST). Nothing has been written for the OSC, so
+    /// this method should write whatever should replace it.
+    fn osc_end(&mut self, terminator: &[u8], write: &mut dyn FnMut(&[u8]));
+
+    /// Called when an intercepted OSC is canceled or ends without a string
+    /// terminator. The terminal ignores such OSCs.
+    fn osc_cancel(&mut self);
+}
+
+/// The phase of an operating system command (OSC).
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum Osc {
+    /// The command number is being received.
+    Command,
+    /// The OSC is being passed to [`Perform::osc_field`].
+    Intercepted,
+    /// The OSC is being forwarded as it arrives.
+    Forwarded,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum State {
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    CsiEntry,
+    CsiParam,
+    CsiIntermediate,
+    CsiIgnore,
+    DcsEntry,
+    DcsParam,
+    DcsIntermediate,
+    /// The data of a device control string. If `tmux_check` is `Some(n)`,
+    /// the string so far (held in the buffer) could still be the start of a
+    /// tmux passthrough sequence (`ESC P tmux;`), and the first `n` bytes of
+    /// the data have matched.
+    DcsPassthrough {
+        tmux_check: Option<usize>,
+    },
+    DcsIgnore,
+    OscString(Osc),
+    /// After an ESC in an intercepted OSC, which ends the OSC if it is part
+    /// of ST.
+    OscEscape,
+    SosPmApcString,
+}
+
+/// The state of a tmux passthrough sequence, whose data is itself terminal
+/// output with each ESC doubled.
+struct Tmux {
+    parser: Parser,
+    /// Whether the previous byte was an (undoubled) ESC.
+    after_esc: bool,
+}
+
+pub struct Parser {
+    c1_mode: C1Mode,
+    state: State,
+    /// Whether the previous byte was 0xc2 in [`C1Mode::Utf8`].
+    after_c2: bool,
+    /// The in-progress sequence, exactly as received.
+    raw: Vec<u8>,
+    private: Option<u8>,
+    params: Vec<u8>,
+    intermediates: Vec<u8>,
+    controls: Vec<u8>,
+    /// The number of subparameters in the current parameter.
+    subparams: usize,
+    /// The number of digits in the current subparameter.
+    digits: usize,
+    /// Whether the current control sequence has been passed to
+    /// [`Perform::csi_stream`]. If so, [`Self::raw`] is no longer updated.
+    streamed: bool,
+    /// Whether the start of the current (ignored) control sequence has been
+    /// forwarded because the sequence became too long.
+    forwarded: bool,
+    /// In the escape state, whether the ESC ended a forwarded control
+    /// string. Unless the ESC is part of ST, ST is written to end the string,
+    /// as what follows the ESC may not be forwarded.
+    ends_string: bool,
+    /// Whether an intercepted OSC has a field in progress (i.e., whether a
+    /// `;` has been received).
+    osc_field: bool,
+    tmux: Option<Box<Tmux>>,
+}
+
+impl Parser {
+    pub fn new(c1_mode: C1Mode) -> Self {
+        Self {
+            c1_mode,
+            state: State::Ground,
+            after_c2: false,
+            raw: Vec::new(),
+            private: None,
+            params: Vec::new(),
+            intermediates: Vec::new(),
+            controls: Vec::new(),
+            subparams: 0,
+            digits: 0,
+            streamed: false,
+            forwarded: false,
+            ends_string: false,
+            osc_field: false,
+            tmux: None,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.raw.clear();
+        self.private = None;
+        self.params.clear();
+        self.intermediates.clear();
+        self.controls.clear();
+        self.subparams = 0;
+        self.digits = 0;
+        self.streamed = false;
+        self.forwarded = false;
+        self.ends_string = false;
+        self.osc_field = false;
+    }
+
+    /// Forwards any in-progress sequence unchanged and returns to the ground
+    /// state.
+    pub fn flush(
+        &mut self,
+        perform: &mut dyn Perform,
+        write: &mut dyn FnMut(&[u8]),
+    ) {
+        if let Some(mut tmux) = self.tmux.take() {
+            tmux.parser.flush(perform, &mut |b| write_tmux_escaped(b, write));
+        }
+        // Streamed sequences that become invalid have already been canceled.
+        if self.streamed && self.state != State::CsiIgnore {
+            perform.csi_cancel(write);
+        }
+        if let State::OscString(Osc::Intercepted) | State::OscEscape =
+            self.state
+        {
+            perform.osc_cancel();
+        }
+        if self.after_c2 {
+            self.raw.push(0xc2);
+            self.after_c2 = false;
+        }
+        write(&self.raw);
+        self.clear();
+        self.state = State::Ground;
+    }
+
+    /// Ends any in-progress sequence because an ESC or a C1 control has
+    /// interrupted it, and returns to the ground state. Returns whether a
+    /// forwarded control string was interrupted, in which case it must still
+    /// be ended with ST.
+    ///
+    /// Unlike [`Self::flush`], this drops an unfinished escape or control
+    /// sequence (other than C0 controls within it), which the terminal would
+    /// discard anyway. If it were forwarded and the sequence that interrupted
+    /// it were removed, the text after that sequence would become part of the
+    /// unfinished one.
+    fn interrupt(
+        &mut self,
+        perform: &mut dyn Perform,
+        write: &mut dyn FnMut(&[u8]),
+    ) -> bool {
+        match self.state {
+            State::Escape
+            | State::EscapeIntermediate
+            | State::CsiEntry
+            | State::CsiParam
+            | State::CsiIntermediate
+            | State::CsiIgnore
+            | State::DcsEntry
+            | State::DcsParam
+            | State::DcsIntermediate => {
+                let ends_string = self.ends_string;
+                if self.streamed && self.state != State::CsiIgnore {
+                    perform.csi_cancel(write);
+                }
+                write(&self.controls);
+                // CAN aborts the part of the sequence that was forwarded.
+                if self.forwarded {
+                    write(b"\x18");
+                }
+                self.clear();
+                self.state = State::Ground;
+                ends_string
+            }
+            // Nothing has been forwarded for these OSCs.
+            State::Ground
+            | State::OscString(Osc::Command | Osc::Intercepted)
+            | State::OscEscape => {
+                self.flush(perform, write);
+                false
+            }
+            _ => {
+                self.flush(perform, write);
+                true
+            }
+        }
+    }
+
+    /// Whether the parser is between sequences, with no data held back.
+    pub fn is_ground(&self) -> bool {
+        self.state == State::Ground && !self.after_c2
+    }
+
+    /// Changes how C1 controls are recognized. This should be called only
+    /// when [`Self::is_ground`] is true.
+    pub fn set_c1_mode(&mut self, c1_mode: C1Mode) {
+        self.c1_mode = c1_mode;
+    }
+
+    /// Whether the parser is in an intercepted OSC, i.e., whether the OSC's
+    /// command number has been received and the rest of the OSC is being
+    /// held back until its string terminator.
+    pub fn is_intercepting_osc(&self) -> bool {
+        matches!(
+            self.state,
+            State::OscString(Osc::Intercepted) | State::OscEscape
+        )
+    }
+
+    /// Begins a new sequence with the given introducer.
+    fn enter(&mut self, state: State, introducer: &[u8]) {
+        self.clear();
+        self.raw.extend_from_slice(introducer);
+        self.state = state;
+    }
+
+    pub fn advance(
+        &mut self,
+        b: u8,
+        perform: &mut dyn Perform,
+        write: &mut dyn FnMut(&[u8]),
+    ) {
+        if let Some(tmux) = &mut self.tmux {
+            let mut write_escaped = |b: &[u8]| write_tmux_escaped(b, write);
+            if !tmux.after_esc {
+                if b == ESC {
+                    tmux.after_esc = true;
+                } else {
+                    tmux.parser.advance(b, perform, &mut write_escaped);
+                }
+                return;
+            }
+            tmux.after_esc = false;
+            if b == ESC {
+                tmux.parser.advance(ESC, perform, &mut write_escaped);
+                return;
+            }
+            // A lone ESC ends the passthrough sequence (normally as part of
+            // ST).
+            self.flush(perform, write);
+            self.enter(State::Escape, &[ESC]);
+            self.ends_string = true;
+        }
+
+        match self.c1_mode {
+            C1Mode::Disabled => {}
+            C1Mode::EightBit => {
+                if let 0x80..=0x9f = b {
+                    return self.c1_control(b, &[b], perform, write);
+                }
+            }
+            C1Mode::Utf8 => {
+                if mem::take(&mut self.after_c2) {
+                    if let 0x80..=0x9f = b {
+                        return self.c1_control(b, &[0xc2, b], perform, write);
+                    }
+                    self.byte(0xc2, perform, write);
+                }
+                if b == 0xc2 {
+                    self.after_c2 = true;
+                    return;
+                }
+            }
+        }
+        self.byte(b, perform, write);
+    }
+
+    /// Handles a C1 control, whose 8-bit value is `c1` and whose encoded form
+    /// is `raw`.
+    fn c1_control(
+        &mut self,
+        c1: u8,
+        raw: &[u8],
+        perform: &mut dyn Perform,
+        write: &mut dyn FnMut(&[u8]),
+    ) {
+        if self.state == State::OscString(Osc::Command) {
+            self.end_osc_command(perform, write);
+        }
+        if c1 == 0x9c && self.state == State::OscString(Osc::Intercepted) {
+            return self.end_osc(raw, perform, write);
+        }
+        if self.interrupt(perform, write) && c1 != 0x9c {
+            write(ST);
+        }
+        match c1 {
+            0x90 => self.enter(State::DcsEntry, raw),
+            0x9b => self.enter(State::CsiEntry, raw),
+            0x9d => self.enter(State::OscString(Osc::Command), raw),
+            0x98 | 0x9e | 0x9f => self.enter(State::SosPmApcString, raw),
+            // ST, and other C1 controls, which cancel sequences but are
+            // otherwise ordinary.
+            _ => write(raw),
+        }
+        self.forward_string_start(write);
+    }
+
+    fn in_string(&self) -> bool {
+        matches!(
+            self.state,
+            State::DcsPassthrough {
+                tmux_check: None,
+            } | State::DcsIgnore
+                | State::OscString(Osc::Forwarded)
+                | State::SosPmApcString
+        )
+    }
+
+    /// Control strings are forwarded as they arrive, so once a string has
+    /// begun, its start doesn't need to be buffered.
+    fn forward_string_start(&mut self, write: &mut dyn FnMut(&[u8])) {
+        if self.in_string() {
+            write(&self.raw);
+            self.clear();
+        }
+    }
+
+    /// Handles a byte that is not a C1 control.
+    fn byte(
+        &mut self,
+        b: u8,
+        perform: &mut dyn Perform,
+        write: &mut dyn FnMut(&[u8]),
+    ) {
+        if self.state == State::OscString(Osc::Command) && !b.is_ascii_digit()
+        {
+            self.end_osc_command(perform, write);
+        }
+        match (self.state, b) {
+            (State::OscString(Osc::Intercepted), 0x07) => {
+                return self.end_osc(&[b], perform, write);
+            }
+            (State::OscString(Osc::Intercepted), ESC) => {
+                self.state = State::OscEscape;
+                return;
+            }
+            (State::OscString(Osc::Intercepted), b';') => {
+                self.osc_field(perform);
+                self.osc_field = true;
+                return;
+            }
+            (State::OscString(Osc::Intercepted), 0x18 | 0x1a) => {}
+            (State::OscString(Osc::Intercepted), _) => {
+                self.osc_field = true;
+                if self.params.len() < SEQUENCE_MAX_LEN {
+                    self.params.push(b);
+                }
+                return;
+            }
+            (State::OscEscape, b'\\') => {
+                self.state = State::OscString(Osc::Intercepted);
+                return self.end_osc(b"\x1b\\", perform, write);
+            }
+            (State::OscEscape, _) => {
+                perform.osc_cancel();
+                self.enter(State::Escape, &[ESC]);
+            }
+            _ => {}
+        }
+
+        match b {
+            // CAN and SUB cancel any sequence.
+            0x18 | 0x1a => {
+                self.flush(perform, write);
+                write(&[b]);
+                return;
+            }
+            ESC => {
+                let ends_string = self.interrupt(perform, write);
+                self.enter(State::Escape, &[ESC]);
+                self.ends_string = ends_string;
+                return;
+            }
+            _ => {}
+        }
+
+        match self.state {
+            State::Ground => return write(&[b]),
+            State::OscString(Osc::Forwarded) if b == 0x07 => {
+                self.state = State::Ground;
+                return write(&[b]);
+            }
+            _ if self.in_string() => return write(&[b]),
+            _ => {}
+        }
+
+        if !self.streamed {
+            self.raw.push(b);
+        }
+        self.sequence_byte(b, perform, write);
+        self.forward_string_start(write);
+        if self.raw.len().max(self.params.len()).max(self.intermediates.len())
+            > SEQUENCE_MAX_LEN
+        {
+            self.overflow(perform, write);
+        }
+    }
+
+    /// Called when the buffered sequence becomes too long.
+    fn overflow(
+        &mut self,
+        perform: &mut dyn Perform,
+        write: &mut dyn FnMut(&[u8]),
+    ) {
+        match self.state {
+            State::CsiEntry | State::CsiParam if self.private.is_none() => {
+                let end = self
+                    .params
+                    .iter()
+                    .rposition(|b| *b == b';')
+                    .map_or(0, |i| i + 1);
+                perform.csi_stream(&self.params[..end], &self.controls, write);
+                self.params.drain(..end);
+                self.raw.clear();
+                self.controls.clear();
+                self.streamed = true;
+            }
+            State::CsiEntry
+            | State::CsiParam
+            | State::CsiIntermediate
+            | State::CsiIgnore => {
+                write(&self.raw);
+                self.raw.clear();
+                self.forwarded = true;
+                self.ignore(perform, write);
+            }
+            State::DcsEntry | State::DcsParam | State::DcsIntermediate => {
+                write(&self.raw);
+                self.clear();
+                self.state = State::DcsIgnore;
+            }
+            // No intercepted OSC has a command number this long.
+            State::OscString(Osc::Command) => {
+                self.state = State::OscString(Osc::Forwarded);
+                self.forward_string_start(write);
+            }
+            _ => {
+                write(&self.raw);
+                self.clear();
+            }
+        }
+    }
+
+    /// Called when the command number of an OSC has been received.
+    fn end_osc_command(
+        &mut self,
+        perform: &mut dyn Perform,
+        write: &mut dyn FnMut(&[u8]),
+    ) {
+        if perform.osc_start(&self.params) {
+            self.clear();
+            self.state = State::OscString(Osc::Intercepted);
+        } else {
+            self.state = State::OscString(Osc::Forwarded);
+            self.forward_string_start(write);
+        }
+    }
+
+    /// Passes the current field of an intercepted OSC to `perform`.
+    fn osc_field(&mut self, perform: &mut dyn Perform) {
+        if self.osc_field {
+            perform.osc_field(&self.params);
+        }
+        self.params.clear();
+    }
+
+    /// Ends an intercepted OSC with the given string terminator.
+    fn end_osc(
+        &mut self,
+        terminator: &[u8],
+        perform: &mut dyn Perform,
+        write: &mut dyn FnMut(&[u8]),
+    ) {
+        self.osc_field(perform);
+        perform.osc_end(terminator, write);
+        self.clear();
+        self.state = State::Ground;
+    }
+
+    /// Stops interpreting the current control sequence or device control
+    /// string, which the terminal will ignore.
+    fn ignore(
+        &mut self,
+        perform: &mut dyn Perform,
+        write: &mut dyn FnMut(&[u8]),
+    ) {
+        if self.is_dcs() {
+            self.state = State::DcsIgnore;
+            return;
+        }
+        if self.streamed && self.state != State::CsiIgnore {
+            perform.csi_cancel(write);
+        }
+        self.params.clear();
+        self.intermediates.clear();
+        self.state = State::CsiIgnore;
+    }
+
+    /// Adds a byte to the current parameters.
+    fn push_param(&mut self, b: u8) {
+        match b {
+            b';' => {
+                self.subparams = 0;
+                self.digits = 0;
+            }
+            b':' => {
+                self.subparams += 1;
+                self.digits = 0;
+            }
+            _ if self.subparams >= PARAM_MAX_SUBPARAMS => return,
+            // Replace a leading zero.
+            _ if self.digits == 1 && self.params.last() == Some(&b'0') => {
+                self.params.pop();
+                self.digits = 0;
+            }
+            _ => {}
+        }
+        if self.subparams >= PARAM_MAX_SUBPARAMS
+            || self.digits >= PARAM_MAX_DIGITS
+        {
+            return;
+        }
+        if b.is_ascii_digit() {
+            self.digits += 1;
+        }
+        self.params.push(b);
+    }
+
+    /// Handles a byte in a buffered sequence. The byte has already been added
+    /// to [`Self::raw`].
+    fn sequence_byte(
+        &mut self,
+        b: u8,
+        perform: &mut dyn Perform,
+        write: &mut dyn FnMut(&[u8]),
+    ) {
+        if let State::DcsPassthrough {
+            tmux_check: Some(matched),
+        } = self.state
+        {
+            return self.check_tmux(b, matched, write);
+        }
+
+        match b {
+            // C0 controls are executed immediately in the middle of
+            // sequences.
+            0x00..=0x1f if self.streamed => return write(&[b]),
+            0x00..=0x1f => return self.controls.push(b),
+            // DEL is ignored.
+            0x7f => return,
+            _ => {}
+        }
+
+        if mem::take(&mut self.ends_string) && b != b'\\' {
+            write(ST);
+        }
+        match self.state {
+            State::Escape => match b {
+                0x20..=0x2f => {
+                    self.intermediates.push(b);
+                    self.state = State::EscapeIntermediate;
+                }
+                b'[' => self.state = State::CsiEntry,
+                b'P' => self.state = State::DcsEntry,
+                b']' => self.state = State::OscString(Osc::Command),
+                b'X' | b'^' | b'_' => self.state = State::SosPmApcString,
+                _ => self.flush(perform, write),
+            },
+            State::EscapeIntermediate => match b {
+                0x20..=0x2f => self.intermediates.push(b),
+                _ => self.flush(perform, write),
+            },
+            State::CsiEntry | State::DcsEntry => match b {
+                0x3c..=0x3f => {
+                    self.private = Some(b);
+                    self.state = self.param_state();
+                }
+                b'0'..=b'9' | b';' | b':' => {
+                    self.push_param(b);
+                    self.state = self.param_state();
+                }
+                _ => self.header_byte(b, perform, write),
+            },
+            State::CsiParam | State::DcsParam => match b {
+                b'0'..=b'9' | b';' | b':' => self.push_param(b),
+                0x3c..=0x3f => self.ignore(perform, write),
+                _ => self.header_byte(b, perform, write),
+            },
+            State::CsiIntermediate | State::DcsIntermediate => match b {
+                0x30..=0x3f => self.ignore(perform, write),
+                _ => self.header_byte(b, perform, write),
+            },
+            State::OscString(Osc::Command) => self.params.push(b),
+            // The terminal ignores these sequences, so there's no need to
+            // inspect them.
+            State::CsiIgnore => {
+                if let 0x40..=0x7e = b {
+                    self.flush(perform, write);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn is_dcs(&self) -> bool {
+        matches!(
+            self.state,
+            State::DcsEntry | State::DcsParam | State::DcsIntermediate
+        )
+    }
+
+    fn param_state(&self) -> State {
+        if self.is_dcs() {
+            State::DcsParam
+        } else {
+            State::CsiParam
+        }
+    }
+
+    /// Handles an intermediate, final, or invalid byte in the header of a
+    /// control sequence or device control string.
+    fn header_byte(
+        &mut self,
+        b: u8,
+        perform: &mut dyn Perform,
+        write: &mut dyn FnMut(&[u8]),
+    ) {
+        let dcs = self.is_dcs();
+        match b {
+            0x20..=0x2f => {
+                self.intermediates.push(b);
+                self.state = if dcs {
+                    State::DcsIntermediate
+                } else {
+                    State::CsiIntermediate
+                };
+            }
+            0x40..=0x7e if dcs => {
+                self.state = State::DcsPassthrough {
+                    tmux_check: (b == b't'
+                        && self.private.is_none()
+                        && self.params.is_empty()
+                        && self.intermediates.is_empty())
+                    .then_some(0),
+                };
+            }
+            0x40..=0x7e => {
+                perform.csi_dispatch(
+                    &Csi {
+                        private: self.private,
+                        params: &self.params,
+                        intermediates: &self.intermediates,
+                        final_byte: b,
+                        controls: &self.controls,
+                        raw: &self.raw,
+                    },
+                    write,
+                );
+                self.clear();
+                self.state = State::Ground;
+            }
+            _ => self.ignore(perform, write),
+        }
+    }
+
+    /// Checks whether the buffered start of a device control string is a tmux
+    /// passthrough sequence (`ESC P tmux;`), given the next byte of the data.
+    fn check_tmux(
+        &mut self,
+        b: u8,
+        matched: usize,
+        write: &mut dyn FnMut(&[u8]),
+    ) {
+        const DATA_PREFIX: &[u8] = b"mux;";
+        let matched = matched + 1;
+        let tmux_check = if DATA_PREFIX[matched - 1] != b {
+            None
+        } else if matched < DATA_PREFIX.len() {
+            Some(matched)
+        } else {
+            write(&self.raw);
+            self.clear();
+            self.tmux = Some(Box::new(Tmux {
+                parser: Parser::new(self.c1_mode),
+                after_esc: false,
+            }));
+            None
+        };
+        self.state = State::DcsPassthrough {
+            tmux_check,
+        };
+    }
+}
+
+/// Writes data inside a tmux passthrough sequence, where each ESC must be
+/// doubled.
+fn write_tmux_escaped(mut data: &[u8], write: &mut dyn FnMut(&[u8])) {
+    while let Some(i) = data.iter().position(|b| *b == ESC) {
+        write(&data[..=i]);
+        write(&[ESC]);
+        data = &data[i + 1..];
+    }
+    write(data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes SGR sequences as `<params>`, intercepts OSC 4 (written as
+    /// `{fields}`), and forwards everything else.
+    #[derive(Default)]
+    struct Recorder {
+        streamed: bool,
+        fields: Vec<u8>,
+    }
+
+    impl Perform for Recorder {
+        fn csi_dispatch(
+            &mut self,
+            csi: &Csi<'_>,
+            write: &mut dyn FnMut(&[u8]),
+        ) {
+            if !csi.is_sgr() {
+                assert!(!mem::take(&mut self.streamed));
+                return write(csi.raw);
+            }
+            write(csi.controls);
+            if !mem::take(&mut self.streamed) {
+                write(b"<");
+            }
+            write(csi.params);
+            write(b">");
+        }
+
+        fn csi_stream(
+            &mut self,
+            params: &[u8],
+            controls: &[u8],
+            write: &mut dyn FnMut(&[u8]),
+        ) {
+            write(controls);
+            if !mem::replace(&mut self.streamed, true) {
+                write(b"<");
+            }
+            write(params);
+        }
+
+        fn csi_cancel(&mut self, write: &mut dyn FnMut(&[u8])) {
+            self.streamed = false;
+            write(b"!");
+        }
+
+        fn osc_start(&mut self, command: &[u8]) -> bool {
+            command == b"4"
+        }
+
+        fn osc_field(&mut self, field: &[u8]) {
+            self.fields.push(b'|');
+            self.fields.extend_from_slice(field);
+        }
+
+        fn osc_end(
+            &mut self,
+            terminator: &[u8],
+            write: &mut dyn FnMut(&[u8]),
+        ) {
+            write(b"{");
+            write(&mem::take(&mut self.fields));
+            write(b"}");
+            write(terminator);
+        }
+
+        fn osc_cancel(&mut self) {
+            self.fields.clear();
+        }
+    }
+
+    /// Parses `input`, split into two chunks at every possible position,
+    /// and checks that the output is `expected` each time.
+    fn check_mode(c1_mode: C1Mode, input: &[u8], expected: &[u8]) {
+        for split in 0..=input.len() {
+            let mut parser = Parser::new(c1_mode);
+            let mut recorder = Recorder::default();
+            let mut output = Vec::new();
+            let mut write = |b: &[u8]| output.extend_from_slice(b);
+            for chunk in [&input[..split], &input[split..]] {
+                for b in chunk.iter().copied() {
+                    parser.advance(b, &mut recorder, &mut write);
+                }
+            }
+            parser.flush(&mut recorder, &mut write);
+            assert_eq!(
+                String::from_utf8_lossy(&output),
+                String::from_utf8_lossy(expected),
+                "split at {split}",
+            );
+        }
+    }
+
+    fn check(input: &[u8], expected: &[u8]) {
+        check_mode(C1Mode::Disabled, input, expected);
+    }
+
+    #[test]
+    fn sgr() {
+        check(b"a\x1b[31mb\x1b[mc", b"a<31>b<>c");
+        check(b"\x1b[0;01;38;5;0196m", b"<0;1;38;5;196>");
+        check(b"\x1b[38:2::255:0:0m", b"<38:2::255:0:0>");
+    }
+
+    #[test]
+    fn double_escape() {
+        // The terminal discards an unfinished sequence.
+        check(b"\x1b\x1b[31mx", b"<31>x");
+        check(b"\x1b(\x1b[31mx", b"<31>x");
+    }
+
+    #[test]
+    fn escape_in_csi() {
+        check(b"\x1b[3\x1b[31mx", b"<31>x");
+        check(b"\x1b[1;\r\x1b[4mx", b"\r<4>x");
+        check(b"\x1b[?1\x1b[4mx", b"<4>x");
+        check(b"\x1bP1$\x1b[4mx", b"<4>x");
+        check_mode(C1Mode::EightBit, b"\x1b[3\x9b31mx", b"<31>x");
+        // If the start of the sequence has been forwarded, CAN aborts it.
+        let input = format!("\x1b[?{}\x1b[4mx", "1;".repeat(100));
+        let mut parser = Parser::new(C1Mode::Disabled);
+        let mut output = Vec::new();
+        for b in input.bytes() {
+            parser.advance(b, &mut Recorder::default(), &mut |b| {
+                output.extend_from_slice(b);
+            });
+        }
+        assert!(output.starts_with(b"\x1b[?1;1;"));
+        assert!(output.ends_with(b"1;\x18<4>x"));
+    }
+
+    #[test]
+    fn cancel_in_csi() {
+        check(b"\x1b[31\x18mx", b"\x1b[31\x18mx");
+        check(b"\x1b[31\x1amx", b"\x1b[31\x1amx");
+    }
+
+    #[test]
+    fn controls_in_csi() {
+        check(b"\x1b[3\r1m", b"\r<31>");
+        check(b"\x1b[3\n1H", b"\x1b[3\n1H");
+    }
+
+    #[test]
+    fn other_sequences() {
+        for input in [
+            &b"\x1b[2J\x1b[1;1H\x1b[?25l\x1b[>4;1m\x1b[ q"[..],
+            b"\x1b[1;1;5;5;1$r\x1b[=5u\x1b(B\x1b#8\x1b7\x1bM",
+            b"\x1b[x\x1b[1:2:3:4:5:6:7:8:9:10x",
+        ] {
+            check(input, input);
+        }
+        // DEL is ignored.
+        check(b"\x1b[1\x7f2m", b"<12>");
+    }
+
+    #[test]
+    fn strings() {
+        // SGR-like data in control strings is not a sequence.
+        check(b"\x1bP1$r31m\x1b\\x", b"\x1bP1$r31m\x1b\\x");
+        check(b"\x1b]0;[31m\x07x", b"\x1b]0;[31m\x07x");
+        check(b"\x1b]0;[31m\x1b\\x", b"\x1b]0;[31m\x1b\\x");
+        check(b"\x1b_[31m\x1b\\x", b"\x1b_[31m\x1b\\x");
+        check(b"\x1b^[31m\x1b\\x", b"\x1b^[31m\x1b\\x");
+        check(b"\x1bX[31m\x1b\\x", b"\x1bX[31m\x1b\\x");
+    }
+
+    #[test]
+    fn sgr_in_strings() {
+        // An ESC ends a control string, so a control sequence that follows
+        // it is interpreted. The string is ended with ST, in case the
+        // sequence is removed.
+        check(b"\x1bPq#0\x1b[31mx", b"\x1bPq#0\x1b\\<31>x");
+        check(b"\x1b]0;title\x1b[31mx", b"\x1b]0;title\x1b\\<31>x");
+        check(b"\x1b_data\x1b[31mx", b"\x1b_data\x1b\\<31>x");
+        check(b"\x1bPtm\x1b[31mx", b"\x1bPtm\x1b\\<31>x");
+        check(b"\x1b]0;t\x1b\x1b[31mx", b"\x1b]0;t\x1b\\<31>x");
+        check_mode(
+            C1Mode::EightBit,
+            b"\x1b]0;t\x9b31mx",
+            b"\x1b]0;t\x1b\\<31>x",
+        );
+    }
+
+    #[test]
+    fn intercepted_osc() {
+        check(b"\x1b]4;1;red\x07x", b"{|1|red}\x07x");
+        check(b"\x1b]4;1;?\x1b\\x", b"{|1|?}\x1b\\x");
+        check(b"\x1b]4;1;red\x18x", b"\x18x");
+        check(b"\x1b]4;1;red\x1b[31mx", b"<31>x");
+        check(b"\x1b]10;?\x07", b"\x1b]10;?\x07");
+    }
+
+    #[test]
+    fn tmux_passthrough() {
+        check(
+            b"\x1bPtmux;\x1b\x1b[31mx\x1b\x1b]0;t\x07\x1b\\y",
+            b"\x1bPtmux;<31>x\x1b\x1b]0;t\x07\x1b\\y",
+        );
+        check(
+            b"\x1bPtmux;\x1b\x1b\x1b\x1b[31m\x1b\\",
+            b"\x1bPtmux;<31>\x1b\\",
+        );
+        check(b"\x1bPtmuxx\x1b[31m", b"\x1bPtmuxx\x1b\\<31>");
+        check(b"\x1bPtmux;x\x1b[31m", b"\x1bPtmux;x\x1b\\<31>");
+    }
+
+    #[test]
+    fn c1_controls() {
+        check_mode(C1Mode::EightBit, b"\x9b31mx", b"<31>x");
+        check_mode(C1Mode::EightBit, b"\x9d0;t\x9cx", b"\x9d0;t\x9cx");
+        check_mode(C1Mode::Utf8, b"\xc2\x9b31m\xc2\xa0", b"<31>\xc2\xa0");
+        check_mode(C1Mode::Utf8, b"\x9b31m", b"\x9b31m");
+        check_mode(C1Mode::Disabled, b"\xc2\x9b31m", b"\xc2\x9b31m");
+    }
+
+    #[test]
+    fn long_sgr() {
+        let params = "1;".repeat(100);
+        let input = format!("\x1b[{params}4m");
+        let expected = format!("<{params}4>");
+        check(input.as_bytes(), expected.as_bytes());
+    }
+
+    #[test]
+    fn long_private_sequence() {
+        let input = format!("\x1b[?{}1hx", "1;".repeat(100));
+        check(input.as_bytes(), input.as_bytes());
+    }
+}
@@ -0,0 +1,191 @@
+/*
+ * Copyright (C) 2026 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of Monoterm.
+ *
+ * Monoterm is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Monoterm is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Monoterm. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Querying the parent terminal's palette.
+
+use crate::color::Rgb;
+use crate::palette::Palette;
+use crate::parser::{C1Mode, Csi, Parser, Perform};
+use nix::errno::Errno;
+use nix::poll::{PollFd, PollFlags, PollTimeout, poll};
+use nix::sys::termios::{FlushArg, SetArg, Termios};
+use nix::sys::termios::{cfmakeraw, tcflush, tcgetattr, tcsetattr};
+use nix::unistd::{isatty, read};
+use std::io::{self, Write};
+use std::os::unix::io::AsFd;
+use std::time::{Duration, Instant};
+
+/// How long to wait for the terminal to start replying to the queries.
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How long to wait for the rest of the replies once the terminal has started
+/// replying (e.g., over a slow connection).
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Collects the colors in the terminal's replies.
+struct Replies<'a> {
+    palette: &'a mut Palette,
+    /// The command number of the OSC being received.
+    command: u16,
+    fields: Vec<Vec<u8>>,
+    /// Whether the reply to the final query (Primary Device Attributes) has
+    /// been received.
+    done: bool,
+}
+
+impl Perform for Replies<'_> {
+    fn csi_dispatch(&mut self, csi: &Csi<'_>, _write: &mut dyn FnMut(&[u8])) {
+        if csi.private == Some(b'?') && csi.final_byte == b'c' {
+            self.done = true;
+        }
+    }
+
+    fn csi_stream(
+        &mut self,
+        _params: &[u8],
+        _controls: &[u8],
+        _write: &mut dyn FnMut(&[u8]),
+    ) {
+    }
+
+    fn csi_cancel(&mut self, _write: &mut dyn FnMut(&[u8])) {}
+
+    fn osc_start(&mut self, command: &[u8]) -> bool {
+        self.command = match command {
+            b"4" => 4,
+            b"10" => 10,
+            b"11" => 11,
+            _ => return false,
+        };
+        self.fields.clear();
+        true
+    }
+
+    fn osc_field(&mut self, field: &[u8]) {
+        self.fields.push(field.to_vec());
+    }
+
+    fn osc_end(&mut self, _terminator: &[u8], _write: &mut dyn FnMut(&[u8])) {
+        let fields = std::mem::take(&mut self.fields);
+        let mut fields = fields.iter();
+        while let Some(field) = fields.next() {
+            let index = match self.command {
+                4 => std::str::from_utf8(field)
+                    .ok()
+                    .and_then(|s| s.parse().ok()),
+                _ => None,
+            };
+            let spec = match self.command {
+                4 => fields.next(),
+                _ => Some(field),
+            };
+            let Some(rgb) = spec.and_then(|s| Rgb::parse(s)) else {
+                continue;
+            };
+            match (self.command, index) {
+                (4, Some(index)) => self.palette.set(index, rgb),
+                (10, _) => self.palette.foreground = rgb,
+                (11, _) => self.palette.background = rgb,
+                _ => {}
+            }
+        }
+    }
+
+    fn osc_cancel(&mut self) {
+        self.fields.clear();
+    }
+}
+
+/// Restores the terminal's attributes when dropped.
+struct RestoreAttrs(Termios);
+
+impl Drop for RestoreAttrs {
+    fn drop(&mut self) {
+        let _ = tcsetattr(io::stdin(), SetArg::TCSANOW, &self.0);
+    }
+}
+
+/// Queries the terminal for its palette (OSC 4) and default colors (OSC 10
+/// and 11), and stores the colors it reports in `palette`. If the terminal
+/// doesn't reply within a short time, or standard input and output aren't a
+/// terminal, `palette` is left unchanged.
+pub fn probe(palette: &mut Palette) {
+    if !(isatty(0).unwrap_or(false) && isatty(1).unwrap_or(false)) {
+        return;
+    }
+    let Ok(attrs) = tcgetattr(io::stdin()) else {
+        return;
+    };
+    let mut raw = attrs.clone();
+    cfmakeraw(&mut raw);
+    if tcsetattr(io::stdin(), SetArg::TCSANOW, &raw).is_err() {
+        return;
+    }
+    let _restore = RestoreAttrs(attrs);
+
+    let mut queries = Vec::new();
+    for i in 0..256 {
+        write!(queries, "\x1b]4;{i};?\x1b\\").unwrap();
+    }
+    // Every terminal replies to Primary Device Attributes (`CSI c`), so once
+    // its reply arrives, there are no more replies to wait for.
+    queries.extend_from_slice(b"\x1b]10;?\x1b\\\x1b]11;?\x1b\\\x1b[c");
+    let mut stdout = io::stdout();
+    if stdout.write_all(&queries).and_then(|_| stdout.flush()).is_err() {
+        return;
+    }
+
+    let mut parser = Parser::new(C1Mode::Disabled);
+    let mut replies = Replies {
+        palette,
+        command: 0,
+        fields: Vec::new(),
+        done: false,
+    };
+    let start = Instant::now();
+    let mut deadline = start + TIMEOUT;
+    let mut buf = [0; 4096];
+    while !replies.done {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let timeout =
+            PollTimeout::try_from(remaining).unwrap_or(PollTimeout::ZERO);
+        let stdin = io::stdin();
+        let mut fds = [PollFd::new(stdin.as_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, timeout) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(Errno::EINTR) => continue,
+            Err(_) => break,
+        }
+        let n = match read(0, &mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(Errno::EINTR) => continue,
+            Err(_) => break,
+        };
+        deadline = start + REPLY_TIMEOUT;
+        for b in buf[..n].iter().copied() {
+            parser.advance(b, &mut replies, &mut |_| {});
+        }
+    }
+    // Discard any replies that arrived after the timeout, so they aren't read
+    // by the child as input. (Key presses during the probe are discarded
+    // too.)
+    let _ = tcflush(io::stdin(), FlushArg::TCIFLUSH);
+}
@@ -0,0 +1,78 @@
+/*
+ * Copyright (C) 2026 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of Monoterm.
+ *
+ * Monoterm is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Monoterm is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Monoterm. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Handling of SIGUSR1 and SIGUSR2, which control a running session.
+
+use nix::errno::Errno;
+use nix::libc::{self, c_int};
+use nix::sys::signal::sigaction;
+use nix::sys::signal::{SaFlags, SigAction, SigHandler, SigSet, Signal};
+use std::io::Read;
+use std::os::fd::IntoRawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread;
+
+/// The socket that the signal handler writes each signal number to.
+static SIGNAL_FD: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn handle_signal(signal: c_int) {
+    // The handler can interrupt any thread, including between a failing
+    // system call and the check of `errno`, so `errno` must be preserved.
+    let errno = Errno::last_raw();
+    let byte = signal as u8;
+    let fd = SIGNAL_FD.load(Ordering::Relaxed);
+    // SAFETY: `write` is async-signal-safe, and `byte` is valid for reads
+    // of one byte. If the socket is full, the signal is dropped.
+    unsafe {
+        libc::write(fd, (&byte as *const u8).cast(), 1);
+    }
+    Errno::set_raw(errno);
+}
+
+/// Calls `on_signal` from a background thread whenever SIGUSR1 or SIGUSR2 is
+/// received.
+pub fn handle<F>(mut on_signal: F) -> Result<(), String>
+where
+    F: FnMut(Signal) + Send + 'static,
+{
+    let error = |e| format!("could not handle signals: {e}");
+    let (mut reader, writer) = UnixStream::pair().map_err(error)?;
+    writer.set_nonblocking(true).map_err(error)?;
+    SIGNAL_FD.store(writer.into_raw_fd(), Ordering::Relaxed);
+    let action = SigAction::new(
+        SigHandler::Handler(handle_signal),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    for signal in [Signal::SIGUSR1, Signal::SIGUSR2] {
+        // SAFETY: `handle_signal` is async-signal-safe.
+        unsafe { sigaction(signal, &action) }
+            .map_err(|e| format!("could not handle signals: {e}"))?;
+    }
+    thread::spawn(move || {
+        let mut byte = 0;
+        while reader.read_exact(std::slice::from_mut(&mut byte)).is_ok() {
+            if let Ok(signal) = Signal::try_from(c_int::from(byte)) {
+                on_signal(signal);
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,330 @@
+/*
+ * Copyright (C) 2026 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of Monoterm.
+ *
+ * Monoterm is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Monoterm is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Monoterm. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Text styles, and the mapping from colors to the styles that replace them.
+
+use crate::color::Color;
+use crate::palette::Palette;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Intensity {
+    High,
+    Low,
+    Normal,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Underline {
+    None,
+    Single,
+    Double,
+    Curly,
+    Dotted,
+    Dashed,
+}
+
+impl Underline {
+    /// Returns the underline style selected by the subparameter in
+    /// `4:<style>`.
+    pub fn from_style(style: u32) -> Option<Self> {
+        Some(match style {
+            0 => Self::None,
+            1 => Self::Single,
+            2 => Self::Double,
+            3 => Self::Curly,
+            4 => Self::Dotted,
+            5 => Self::Dashed,
+            _ => return None,
+        })
+    }
+
+    /// Returns the SGR argument that selects this underline style.
+    pub fn sgr_arg(self) -> &'static [u8] {
+        match self {
+            Self::None => b"24",
+            Self::Single => b"4",
+            // More terminals support `21` than `4:2`.
+            Self::Double => b"21",
+            Self::Curly => b"4:3",
+            Self::Dotted => b"4:4",
+            Self::Dashed => b"4:5",
+        }
+    }
+}
+
+/// The attributes of text other than its colors and video reversal.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Style {
+    pub intensity: Intensity,
+    pub italic: bool,
+    pub underline: Underline,
+    pub blink: bool,
+    pub strikethrough: bool,
+    pub overline: bool,
+}
+
+impl Style {
+    pub const DEFAULT: Self = Self {
+        intensity: Intensity::Normal,
+        italic: false,
+        underline: Underline::None,
+        blink: false,
+        strikethrough: false,
+        overline: false,
+    };
+
+    /// Adds the attributes of `other` that aren't already set in `self`.
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            intensity: match self.intensity {
+                Intensity::Normal => other.intensity,
+                intensity => intensity,
+            },
+            italic: self.italic || other.italic,
+            underline: match self.underline {
+                Underline::None => other.underline,
+                underline => underline,
+            },
+            blink: self.blink || other.blink,
+            strikethrough: self.strikethrough || other.strikethrough,
+            overline: self.overline || other.overline,
+        }
+    }
+
+    /// Parses a `+`-separated list of attributes, e.g., `bold+underline`, or
+    /// `none`.
+    fn parse(spec: &str) -> Option<Self> {
+        let mut style = Self::DEFAULT;
+        if spec == "none" {
+            return Some(style);
+        }
+        for attr in spec.split('+') {
+            match attr {
+                "bold" => style.intensity = Intensity::High,
+                "faint" => style.intensity = Intensity::Low,
+                "italic" => style.italic = true,
+                "underline" => style.underline = Underline::Single,
+                "double-underline" => style.underline = Underline::Double,
+                "curly-underline" => style.underline = Underline::Curly,
+                "dotted-underline" => style.underline = Underline::Dotted,
+                "dashed-underline" => style.underline = Underline::Dashed,
+                "blink" => style.blink = true,
+                "strikethrough" => style.strikethrough = true,
+                "overline" => style.overline = true,
+                _ => return None,
+            }
+        }
+        Some(style)
+    }
+}
+
+impl fmt::Display for Style {
+    /// Formats the style as a mapping rule specifies it, e.g.,
+    /// `bold+underline` or `none`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let underline = match self.underline {
+            Underline::None => None,
+            Underline::Single => Some("underline"),
+            Underline::Double => Some("double-underline"),
+            Underline::Curly => Some("curly-underline"),
+            Underline::Dotted => Some("dotted-underline"),
+            Underline::Dashed => Some("dashed-underline"),
+        };
+        let attrs = [
+            (self.intensity == Intensity::High).then_some("bold"),
+            (self.intensity == Intensity::Low).then_some("faint"),
+            self.italic.then_some("italic"),
+            underline,
+            self.blink.then_some("blink"),
+            self.strikethrough.then_some("strikethrough"),
+            self.overline.then_some("overline"),
+        ];
+        let mut attrs = attrs.into_iter().flatten().peekable();
+        if attrs.peek().is_none() {
+            return f.write_str("none");
+        }
+        for (i, attr) in attrs.enumerate() {
+            if i > 0 {
+                f.write_str("+")?;
+            }
+            f.write_str(attr)?;
+        }
+        Ok(())
+    }
+}
+
+/// The names of the 16 basic colors.
+pub const COLOR_NAMES: [&str; 16] = [
+    "black",
+    "red",
+    "green",
+    "yellow",
+    "blue",
+    "magenta",
+    "cyan",
+    "white",
+    "bright-black",
+    "bright-red",
+    "bright-green",
+    "bright-yellow",
+    "bright-blue",
+    "bright-magenta",
+    "bright-cyan",
+    "bright-white",
+];
+
+/// Returns whether `name` matches `pattern`, in which `*` matches any
+/// sequence of characters.
+fn glob(pattern: &str, name: &str) -> bool {
+    let Some((prefix, rest)) = pattern.split_once('*') else {
+        return pattern == name;
+    };
+    let Some(name) = name.strip_prefix(prefix) else {
+        return false;
+    };
+    (0..=name.len())
+        .filter(|i| name.is_char_boundary(*i))
+        .any(|i| glob(rest, &name[i..]))
+}
+
+/// Colors in the palette, as specified in a mapping rule or [`ColorSet`].
+#[derive(Clone, Debug)]
+enum Key {
+    /// A single entry in the 256-color palette.
+    Index(u8),
+    /// A pattern matching the names of basic colors, e.g., `bright*`.
+    Pattern(String),
+}
+
+impl Key {
+    /// Parses a palette index or a pattern matching basic color names.
+    fn parse(key: &str) -> Result<Self, String> {
+        if let Ok(index) = key.parse() {
+            Ok(Self::Index(index))
+        } else if COLOR_NAMES.iter().any(|name| glob(key, name)) {
+            Ok(Self::Pattern(key.to_owned()))
+        } else {
+            Err(format!("unknown color: {key}"))
+        }
+    }
+
+    /// Returns whether this key includes the palette entry `index`.
+    fn matches(&self, index: u8) -> bool {
+        match self {
+            Self::Index(i) => *i == index,
+            Self::Pattern(pattern) => COLOR_NAMES
+                .get(usize::from(index))
+                .is_some_and(|name| glob(pattern, name)),
+        }
+    }
+}
+
+/// A table of the styles that replace foreground colors.
+#[derive(Clone, Debug, Default)]
+pub struct ColorMap(Vec<(Key, Style)>);
+
+impl ColorMap {
+    /// Parses a comma-separated list of rules of the form `<key>=<style>`,
+    /// where `<key>` is a palette index or a pattern matching basic color
+    /// names. Later rules take precedence.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut map = Self::default();
+        for item in spec.split(',') {
+            let error = || format!("invalid color mapping: {item}");
+            let (key, style) = item.split_once('=').ok_or_else(error)?;
+            let key = Key::parse(key)?;
+            let style = Style::parse(style)
+                .ok_or_else(|| format!("invalid style: {style}"))?;
+            map.0.push((key, style));
+        }
+        Ok(map)
+    }
+
+    /// Returns whether the table has no rules.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the style that replaces `color`, if any rule applies to it.
+    /// Colors outside the basic 16 match the patterns of the basic color
+    /// they're closest to, and direct colors match the indices of the
+    /// closest palette entries.
+    pub fn get(&self, color: Color, palette: &Palette) -> Option<Style> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let (index, basic) = match color {
+            Color::Indexed(i @ 0..=15) => (i, i),
+            Color::Indexed(i) => (i, palette.nearest(palette.get(i), 0..=15)),
+            Color::Rgb(rgb) => {
+                (palette.nearest(rgb, 0..=255), palette.nearest(rgb, 0..=15))
+            }
+        };
+        let name = COLOR_NAMES[usize::from(basic)];
+        self.0.iter().rev().find_map(|(key, style)| {
+            match key {
+                Key::Index(i) => match color {
+                    Color::Indexed(_) => *i == index,
+                    // Entries with the same color (e.g., 9 and 196 in the
+                    // default palette) are equally close.
+                    Color::Rgb(_) => palette.get(*i) == palette.get(index),
+                },
+                Key::Pattern(pattern) => glob(pattern, name),
+            }
+            .then_some(*style)
+        })
+    }
+}
+
+/// The maximum perceptual distance (CIE 1976 delta E) between a color and a
+/// color in a [`ColorSet`] for the color to be considered part of the set.
+const SET_MAX_DISTANCE: f64 = 20.0;
+
+/// A set of palette colors, along with the colors close to them.
+#[derive(Clone, Debug, Default)]
+pub struct ColorSet(Vec<Key>);
+
+impl ColorSet {
+    /// Parses a comma-separated list of palette indices and patterns
+    /// matching basic color names.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        spec.split(',').map(Key::parse).collect::<Result<_, _>>().map(Self)
+    }
+
+    /// Returns whether the set has no colors.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns whether `color` is one of the colors in the set or is close
+    /// to one of them.
+    pub fn contains(&self, color: Color, palette: &Palette) -> bool {
+        if self.0.is_empty() {
+            return false;
+        }
+        let rgb = palette.rgb(color);
+        (0..=255).filter(|i| self.0.iter().any(|key| key.matches(*i))).any(
+            |i| {
+                color == Color::Indexed(i)
+                    || palette.distance(rgb, i) <= SET_MAX_DISTANCE
+            },
+        )
+    }
+}
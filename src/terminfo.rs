@@ -0,0 +1,126 @@
+/*
+ * Copyright (C) 2026 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of Monoterm.
+ *
+ * Monoterm is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Monoterm is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Monoterm. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A terminfo entry for the filtered session, derived from the parent
+//! terminal's.
+
+use crate::filter::Mode;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Capabilities that change the palette or set direct (truecolor) colors,
+/// which are removed in every mode.
+const PALETTE_CAPS: &[&str] =
+    &["ccc", "initc", "initp", "oc", "RGB", "Tc", "setrgbf", "setrgbb"];
+
+/// Capabilities that set colors, which are removed in mono mode unless some
+/// colors are kept.
+const COLOR_CAPS: &[&str] =
+    &["colors", "pairs", "setaf", "setab", "setf", "setb", "scp", "op"];
+
+/// Returns the terminfo source of the entry `name`, which is `term`'s entry
+/// adapted to `mode` and to whether some colors are kept.
+fn source(name: &str, term: &str, mode: Mode, keep_colors: bool) -> String {
+    let mut caps: Vec<_> =
+        PALETTE_CAPS.iter().map(|cap| format!("{cap}@")).collect();
+    match mode {
+        Mode::Mono if !keep_colors => {
+            caps.extend(COLOR_CAPS.iter().map(|cap| format!("{cap}@")))
+        }
+        Mode::Mono | Mode::Grayscale => {}
+        Mode::Downsample(n) => caps.push(format!("colors#{n}")),
+    }
+    // Changes must precede `use` to take precedence over it.
+    caps.push(format!("use={term}"));
+    format!("{name}|{term} filtered by monoterm,\n\t{},\n", caps.join(", "))
+}
+
+/// Compiles a terminfo entry for the filtered session with `tic`, installing
+/// it in `~/.terminfo` (or `$TERMINFO`), and returns its name. The entry is
+/// derived from the entry of the parent terminal's `TERM`. In mono mode, it
+/// keeps colors if `keep_colors` is true (i.e., if some colors are kept).
+pub fn install(mode: Mode, keep_colors: bool) -> Result<String, String> {
+    let term = env::var("TERM").unwrap_or_default();
+    let valid = |c: char| c.is_ascii_alphanumeric() || "+-._".contains(c);
+    if term.is_empty() || !term.chars().all(valid) {
+        return Err(format!("unsupported TERM for terminfo entry: {term:?}"));
+    }
+    let name = match mode {
+        Mode::Mono if keep_colors => format!("monoterm-mono-keep-{term}"),
+        Mode::Mono => format!("monoterm-mono-{term}"),
+        Mode::Grayscale => format!("monoterm-gray-{term}"),
+        Mode::Downsample(n) => format!("monoterm-{n}-{term}"),
+    };
+    let dir = match env::var_os("TERMINFO") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME").ok_or("HOME is not set")?)
+            .join(".terminfo"),
+    };
+    fs::create_dir_all(&dir).map_err(|e| {
+        format!("could not install terminfo entry {name}: {e}")
+    })?;
+    // `-` makes `tic` read the source from standard input.
+    let error = |e| format!("could not run tic: {e}");
+    let mut tic = Command::new("tic")
+        .arg("-x")
+        .arg("-o")
+        .arg(&dir)
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(error)?;
+    // The source is far smaller than a pipe's buffer, so this can't block
+    // while `tic` waits for its output to be read.
+    let written = tic
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(source(&name, &term, mode, keep_colors).as_bytes());
+    let output = tic.wait_with_output().map_err(error)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(match stderr.trim() {
+            "" => format!("tic failed: {}", output.status),
+            stderr => format!("tic failed: {stderr}"),
+        });
+    }
+    written.map_err(error)?;
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_caps() {
+        let source = |mode, keep_colors| source("x", "y", mode, keep_colors);
+        assert!(source(Mode::Mono, false).contains(", setaf@,"));
+        assert!(!source(Mode::Mono, true).contains("setaf"));
+        assert!(!source(Mode::Grayscale, false).contains("setaf"));
+        let source = source(Mode::Downsample(16), false);
+        assert!(source.contains(", colors#16, use=y,"));
+        assert!(source.contains("setrgbf@"));
+    }
+}
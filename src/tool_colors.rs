@@ -0,0 +1,319 @@
+/*
+ * Copyright (C) 2026 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of Monoterm.
+ *
+ * Monoterm is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Monoterm is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Monoterm. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Color settings for `ls`, `grep`, `gcc`, and `git` that use text
+//! attributes instead of colors.
+
+use crate::filter::SgrSettings;
+use std::env;
+
+/// The default `LS_COLORS` (from `dircolors`), used if it isn't set.
+const LS_COLORS: &str = "rs=0:di=01;34:ln=01;36:mh=00:pi=40;33:so=01;35:\
+    do=01;35:bd=40;33;01:cd=40;33;01:or=40;31;01:mi=00:su=37;41:sg=30;43:\
+    ca=00:tw=30;42:ow=34;42:st=37;44:ex=01;32";
+
+/// The default `GREP_COLORS`, used if it isn't set.
+const GREP_COLORS: &str = "ms=01;31:mc=01;31:sl=:cx=:fn=35:ln=32:bn=32:se=36";
+
+/// The default `GCC_COLORS`, used if it isn't set.
+const GCC_COLORS: &str = "error=01;31:warning=01;35:note=01;36:range1=32:\
+    range2=34:locus=01:quote=01:path=01;36:fixit-insert=32:fixit-delete=31:\
+    diff-filename=01:diff-hunk=32:diff-delete=31:diff-insert=32:\
+    type-diff=01;32";
+
+/// The text attributes, as SGR parameters, of `LS_COLORS` items that would
+/// otherwise all become bold, used if no color mapping is given.
+const LS_STYLES: &[(&str, &str)] = &[("di", "01"), ("ln", "04"), ("ex", "03")];
+
+/// The text attributes of `GREP_COLORS` items, like [`LS_STYLES`].
+const GREP_STYLES: &[(&str, &str)] = &[("ms", "07"), ("mc", "07")];
+
+/// Git's color settings and their default colors, as SGR parameters.
+const GIT_COLORS: &[(&str, &str)] = &[
+    ("color.diff.meta", "1"),
+    ("color.diff.frag", "36"),
+    ("color.diff.old", "31"),
+    ("color.diff.new", "32"),
+    ("color.diff.commit", "33"),
+    ("color.diff.whitespace", "41"),
+    ("color.diff.oldMoved", "1;35"),
+    ("color.diff.newMoved", "1;36"),
+    ("color.status.added", "32"),
+    ("color.status.updated", "32"),
+    ("color.status.changed", "31"),
+    ("color.status.untracked", "31"),
+    ("color.status.branch", "32"),
+    ("color.status.nobranch", "31"),
+    ("color.status.unmerged", "31"),
+    ("color.branch.current", "32"),
+    ("color.branch.remote", "31"),
+    ("color.branch.upstream", "34"),
+    ("color.decorate.branch", "1;32"),
+    ("color.decorate.remoteBranch", "1;31"),
+    ("color.decorate.tag", "1;33"),
+    ("color.decorate.stash", "1;35"),
+    ("color.decorate.HEAD", "1;36"),
+    ("color.grep.filename", "35"),
+    ("color.grep.lineNumber", "32"),
+    ("color.grep.column", "32"),
+    ("color.grep.match", "1;31"),
+    ("color.grep.separator", "36"),
+    ("color.interactive.prompt", "1;34"),
+    ("color.interactive.header", "1"),
+    ("color.interactive.help", "1;31"),
+    ("color.interactive.error", "1;31"),
+];
+
+/// Git colors for settings in [`GIT_COLORS`], like [`LS_STYLES`].
+const GIT_STYLES: &[(&str, &str)] = &[
+    ("color.diff.old", "dim"),
+    ("color.diff.new", "bold"),
+    ("color.grep.match", "reverse"),
+];
+
+/// Converts each `<key>=<params>` item in a `:`-separated list, like
+/// `LS_COLORS`, with [`SgrSettings::mono_sgr`], or replaces its parameters
+/// with those for `<key>` in `styles`. Other items are unchanged.
+fn convert_list(
+    list: &str,
+    styles: &[(&str, &str)],
+    settings: &SgrSettings,
+) -> String {
+    let convert = |item: &str| {
+        let Some((key, params)) = item.split_once('=') else {
+            return item.to_owned();
+        };
+        let is_sgr = |b: u8| b.is_ascii_digit() || b == b';' || b == b':';
+        if params.is_empty() || !params.bytes().all(is_sgr) {
+            return item.to_owned();
+        }
+        if let Some((_, style)) = styles.iter().find(|(k, _)| *k == key) {
+            return format!("{key}={style}");
+        }
+        let sgr = settings.mono_sgr(params);
+        if sgr.is_empty() {
+            return format!("{key}=0");
+        }
+        // Colons separate the items, so underline styles (e.g., `4:3`)
+        // become plain underlines.
+        let args: Vec<_> = sgr
+            .split(';')
+            .map(|arg| match arg.split_once(':') {
+                Some((n, _)) => n,
+                None => arg,
+            })
+            .collect();
+        format!("{key}={}", args.join(";"))
+    };
+    list.split(':').map(convert).collect::<Vec<_>>().join(":")
+}
+
+/// Converts SGR parameters (as returned by [`SgrSettings::mono_sgr`]) to a Git
+/// color, e.g., `bold ul` or `normal`.
+fn git_color(params: &str) -> String {
+    let mut attrs = Vec::new();
+    let mut colors = [None, None];
+    let mut args = params.split(';');
+    while let Some(arg) = args.next() {
+        let mut subparams = arg.split(':');
+        let Some(Ok(n)) = subparams.next().map(str::parse::<u32>) else {
+            continue;
+        };
+        let attr = match n {
+            1 => "bold",
+            2 => "dim",
+            3 => "italic",
+            4 | 21 => "ul",
+            5 | 6 => "blink",
+            7 => "reverse",
+            9 => "strike",
+            30..=37 | 40..=47 => {
+                colors[(n / 10 - 3) as usize] = Some((n % 10).to_string());
+                continue;
+            }
+            90..=97 | 100..=107 => {
+                let i = (n / 10 - 9) as usize;
+                colors[i] = Some((n % 10 + 8).to_string());
+                continue;
+            }
+            38 | 48 | 58 => {
+                // Extended colors are in either the colon-separated form or
+                // the semicolon-separated form.
+                let mut rest: Vec<_> = subparams.collect();
+                if rest.is_empty() {
+                    let len = match args.next() {
+                        Some("5") => 1,
+                        Some("2") => 3,
+                        _ => continue,
+                    };
+                    rest = args.by_ref().take(len).collect();
+                    rest.insert(
+                        0,
+                        if len == 1 {
+                            "5"
+                        } else {
+                            "2"
+                        },
+                    );
+                }
+                let values: Vec<u8> =
+                    rest.iter().filter_map(|v| v.parse().ok()).collect();
+                let color = match (rest.first(), &values[..]) {
+                    (Some(&"5"), [5, i]) => i.to_string(),
+                    (Some(&"2"), [.., r, g, b]) => {
+                        format!("#{r:02x}{g:02x}{b:02x}")
+                    }
+                    _ => continue,
+                };
+                if n != 58 {
+                    colors[(n / 10 - 3) as usize] = Some(color);
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        attrs.push(attr.to_owned());
+    }
+    let mut words = Vec::new();
+    match colors {
+        [None, None] => {}
+        [fg, bg] => {
+            words.push(fg.unwrap_or_else(|| "normal".to_owned()));
+            words.extend(bg);
+        }
+    }
+    words.extend(attrs);
+    if words.is_empty() {
+        return "normal".to_owned();
+    }
+    words.join(" ")
+}
+
+/// Quotes `s` for `GIT_CONFIG_PARAMETERS`, like a shell word in single
+/// quotes.
+fn git_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Sets `LS_COLORS`, `GREP_COLORS`, `GCC_COLORS`, and Git's colors (through
+/// `GIT_CONFIG_PARAMETERS`) in this process's environment, which the child
+/// inherits, to text attributes derived from their colors with `settings`.
+/// Values already in the environment are converted instead of the defaults,
+/// except for Git's. Unless `settings` has a color mapping, some items get
+/// fixed attributes instead (e.g., directories in `ls` are bold and symbolic
+/// links are underlined), since their colors would all become the same.
+///
+/// This must be called before any other threads are started.
+pub fn apply(settings: &SgrSettings) {
+    let styles = |styles: &'static [(&str, &str)]| {
+        if settings.color_map.is_empty() {
+            styles
+        } else {
+            &[]
+        }
+    };
+    for (var, default, var_styles) in [
+        ("LS_COLORS", LS_COLORS, LS_STYLES),
+        ("GREP_COLORS", GREP_COLORS, GREP_STYLES),
+        ("GCC_COLORS", GCC_COLORS, &[]),
+    ] {
+        let value = env::var(var).unwrap_or_else(|_| default.to_owned());
+        let value = convert_list(&value, styles(var_styles), settings);
+        env::set_var(var, value);
+    }
+
+    let git_styles = styles(GIT_STYLES);
+    let mut params: Vec<_> = GIT_COLORS
+        .iter()
+        .map(|(key, sgr)| {
+            let color = match git_styles.iter().find(|(k, _)| k == key) {
+                Some((_, color)) => (*color).to_owned(),
+                None => git_color(&settings.mono_sgr(sgr)),
+            };
+            git_quote(&format!("{key}={color}"))
+        })
+        .collect();
+    // Settings already given this way take precedence.
+    if let Ok(existing) = env::var("GIT_CONFIG_PARAMETERS") {
+        params.push(existing);
+    }
+    env::set_var("GIT_CONFIG_PARAMETERS", params.join(" "));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Mode;
+    use crate::palette::Palette;
+    use crate::style::{ColorMap, ColorSet};
+
+    fn settings(color_map: &str) -> SgrSettings {
+        SgrSettings {
+            mode: Mode::Mono,
+            bold_colors: false,
+            emphasis: false,
+            color_map: if color_map.is_empty() {
+                ColorMap::default()
+            } else {
+                ColorMap::parse(color_map).unwrap()
+            },
+            keep: ColorSet::default(),
+            truecolor_grays: false,
+            levels: None,
+            palette: Palette::default(),
+        }
+    }
+
+    #[test]
+    fn convert() {
+        let list = "rs=0:di=01;34:ln=01;36:mh=00:ex=01;32:sl=:*.tar=01;31:ne";
+        assert_eq!(
+            convert_list(list, &[], &settings("")),
+            "rs=0:di=1:ln=1:mh=0:ex=1:sl=:*.tar=1:ne",
+        );
+        assert_eq!(
+            convert_list(list, LS_STYLES, &settings("")),
+            "rs=0:di=01:ln=04:mh=0:ex=03:sl=:*.tar=1:ne",
+        );
+        assert_eq!(
+            convert_list(list, &[], &settings("blue=curly-underline")),
+            "rs=0:di=1;4:ln=1:mh=0:ex=1:sl=:*.tar=1:ne",
+        );
+        assert_eq!(
+            convert_list(GREP_COLORS, GREP_STYLES, &settings("")),
+            "ms=07:mc=07:sl=:cx=:fn=0:ln=0:bn=0:se=0",
+        );
+    }
+
+    #[test]
+    fn git() {
+        assert_eq!(git_color(""), "normal");
+        assert_eq!(git_color("1;4"), "bold ul");
+        assert_eq!(
+            git_color("2;3;21;5;7;9"),
+            "dim italic ul blink reverse strike"
+        );
+        assert_eq!(git_color("31;42"), "1 2");
+        assert_eq!(git_color("94;1"), "12 bold");
+        assert_eq!(git_color("48;5;3"), "normal 3");
+        assert_eq!(git_color("38;5;100;48:2::1:2:3"), "100 #010203");
+        assert_eq!(git_color("38;2;1;2;3;58;5;4;4"), "#010203 ul");
+        assert_eq!(git_color("38;5"), "normal");
+    }
+}
@@ -0,0 +1,160 @@
+/*
+ * Copyright (C) 2026 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of Monoterm.
+ *
+ * Monoterm is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Monoterm is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Monoterm. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Watching the configuration file for changes.
+
+use nix::errno::Errno;
+use nix::sys::inotify::WatchDescriptor;
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, InotifyEvent};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+/// The events in a watched directory that change a file in it. Creating the
+/// file isn't a change by itself, as the new file is empty until it is
+/// written and closed.
+const CHANGE_EVENTS: AddWatchFlags = AddWatchFlags::IN_CLOSE_WRITE
+    .union(AddWatchFlags::IN_MOVED_TO)
+    .union(AddWatchFlags::IN_MOVED_FROM)
+    .union(AddWatchFlags::IN_DELETE);
+
+/// The events that directories are watched for: [`CHANGE_EVENTS`], and the
+/// creation of missing directories on the way to a file.
+const WATCH_FLAGS: AddWatchFlags =
+    CHANGE_EVENTS.union(AddWatchFlags::IN_CREATE);
+
+/// A file whose directory is watched. The file's directory is watched rather
+/// than the file itself, as editors often save files by replacing them.
+struct Target {
+    path: PathBuf,
+    /// The directory being watched: the file's directory or, while that
+    /// doesn't exist, its closest ancestor that does.
+    watched: Option<(PathBuf, WatchDescriptor)>,
+}
+
+impl Target {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            watched: None,
+        }
+    }
+
+    /// Watches the file's directory, or its closest existing ancestor.
+    fn watch(&mut self, inotify: &Inotify) -> Result<(), Errno> {
+        let dir = self.path.parent().unwrap_or(Path::new("/"));
+        // A directory can be created just before its parent is watched, so
+        // this repeats until the closest existing one is watched.
+        loop {
+            let Some(existing) = dir.ancestors().find(|d| d.is_dir()) else {
+                return Err(Errno::ENOENT);
+            };
+            if self.watched.as_ref().is_some_and(|(d, _)| d == existing) {
+                return Ok(());
+            }
+            let wd = inotify.add_watch(existing, WATCH_FLAGS)?;
+            self.watched = Some((existing.to_owned(), wd));
+        }
+    }
+
+    /// Returns whether the file's own directory is watched.
+    fn is_dir_watched(&self) -> bool {
+        self.watched.as_ref().map(|(d, _)| &**d) == self.path.parent()
+    }
+
+    /// Handles `event`, returning whether it changed the file.
+    fn handle(&mut self, event: &InotifyEvent, inotify: &Inotify) -> bool {
+        match &self.watched {
+            Some((_, wd)) if *wd == event.wd => {}
+            _ => return false,
+        }
+        if event.mask.contains(AddWatchFlags::IN_IGNORED) {
+            // The directory was deleted.
+            self.watched = None;
+        } else if self.is_dir_watched() {
+            return event.mask.intersects(CHANGE_EVENTS)
+                && event.name.as_deref() == self.path.file_name();
+        }
+        // A missing directory may have been created, possibly along with the
+        // file.
+        let _ = self.watch(inotify);
+        self.is_dir_watched() && self.path.exists()
+    }
+}
+
+/// If `path` is a symbolic link, sets `link_target` to the file it points to
+/// (which changes if the link is replaced) and watches that file.
+fn resolve(
+    path: &Path,
+    link_target: &mut Option<Target>,
+    inotify: &Inotify,
+) -> Result<(), Errno> {
+    let real = fs::canonicalize(path).ok().filter(|p| p != path);
+    if real.as_ref() != link_target.as_ref().map(|t| &t.path) {
+        *link_target = real.map(Target::new);
+    }
+    match link_target {
+        Some(target) => target.watch(inotify),
+        None => Ok(()),
+    }
+}
+
+/// Calls `on_change` from a background thread whenever the file at `path` is
+/// written, replaced, or deleted, including once it is created in a
+/// directory that doesn't exist yet. If `path` is a symbolic link, changes
+/// to the file it points to count too.
+pub fn watch<F>(path: &Path, mut on_change: F) -> Result<(), String>
+where
+    F: FnMut() + Send + 'static,
+{
+    let error = |e| format!("could not watch {}: {e}", path.display());
+    if path.file_name().is_none() {
+        return Err(error(Errno::EINVAL));
+    }
+    let inotify = Inotify::init(InitFlags::IN_CLOEXEC).map_err(error)?;
+    let path = env::current_dir().map_or(path.to_owned(), |d| d.join(path));
+    let mut file = Target::new(path.clone());
+    file.watch(&inotify).map_err(error)?;
+    let mut link_target = None;
+    resolve(&path, &mut link_target, &inotify).map_err(error)?;
+    thread::spawn(move || {
+        loop {
+            let events = match inotify.read_events() {
+                Ok(events) => events,
+                Err(Errno::EINTR) => continue,
+                Err(_) => return,
+            };
+            // Several events (e.g., from writing the file and then closing
+            // it) can arrive together; handle them as one change.
+            let mut changed = false;
+            for event in &events {
+                changed |= file.handle(event, &inotify);
+                if let Some(target) = &mut link_target {
+                    changed |= target.handle(event, &inotify);
+                }
+            }
+            if changed {
+                let _ = resolve(&path, &mut link_target, &inotify);
+                on_change();
+            }
+        }
+    });
+    Ok(())
+}